@@ -22,14 +22,15 @@ layout(set = 0, binding = 0) uniform WorldData {
 layout(set = 1, binding = 0) uniform ModelData {
     mat4 model_matrix;
     mat4 normal_matrix;
+    vec4 color;
 } model_data;
 
 void main() {
     mat4 modelview = world_data.view_matrix * model_data.model_matrix;
     vec4 world_position = world_data.projection_matrix * modelview * vec4(position, 1.0);
     vec3 worldspace_normal = mat3(model_data.normal_matrix) * normal;
-    frag_ambient = ambient;
-    frag_diffuse = diffuse;
+    frag_ambient = ambient * model_data.color.rgb;
+    frag_diffuse = diffuse * model_data.color.rgb;
     frag_position = (model_data.model_matrix * vec4(position, 1.0)).xyz;
     frag_normal = worldspace_normal;
     frag_specular = specular_exponent;
@@ -0,0 +1,485 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use image::RgbaImage;
+use log::{error, info};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBuffer, DynamicState};
+use vulkano::format::Format;
+use vulkano::framebuffer::{FramebufferAbstract, RenderPassAbstract};
+use vulkano::sync::GpuFuture;
+use vulkano_win::VkSurfaceBuild;
+use winit::dpi::LogicalSize;
+use winit::{
+    ElementState, Event, EventsLoop, KeyboardInput, VirtualKeyCode, WindowBuilder, WindowEvent,
+};
+
+use super::config::{ConfigChanges, ConfigWatcher, RendererConfig};
+use super::input::Input;
+use super::metrics::FPSCounter;
+use super::offscreen::{select_readable_format, OffscreenTarget};
+use super::presenter::{report_screenshot, FramesInFlight, Presenter};
+use super::recording::{Recorder, Recording};
+use super::resources::pipelines::create_render_pass;
+use super::time::{Clock, FixedTimestep};
+use super::{Renderer, Result};
+
+/// An application driven by `run`, which owns the window and the frame loop.
+pub trait App {
+    /// The title of the window, followed by the frame rate once it is measured.
+    fn title(&self) -> &str {
+        "Vulkan boilerplate"
+    }
+
+    /// Called once before the first frame. `render_pass` is the one the frames are
+    /// recorded in, see `resources::pipelines::create_render_pass`.
+    fn init(
+        &mut self,
+        renderer: &mut Renderer,
+        render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    ) -> Result<()>;
+
+    /// Called every frame before rendering, with the time elapsed since the previous
+    /// frame in seconds, as measured by `clock`. Meant for input and camera handling;
+    /// the simulation belongs in `fixed_update`.
+    fn update(&mut self, _dt: f32, _input: &Input, _clock: &mut Clock) {}
+
+    /// Called `RendererConfig::update_rate` times per second of the clock, whatever
+    /// the frame rate, with `dt` always being `1 / update_rate`. Not called while the
+    /// clock is paused.
+    fn fixed_update(&mut self, _dt: f32) {}
+
+    /// Records the draw commands of the frame, inside the render pass.
+    fn render(&mut self, context: &mut FrameContext) -> Result<()>;
+
+    /// Called for every window event, after the renderer handled it. The clock ticks
+    /// after the events, so pausing it here applies to the current frame.
+    fn on_event(&mut self, _event: &Event, _clock: &mut Clock) {}
+
+    /// Called before rendering a frame when the swapchain was created or recreated,
+    /// after a resize or a change of `ApplicationState::need_recreation`. Meant to
+    /// rebuild the pipelines that depend on the configuration.
+    fn on_recreated(&mut self, _renderer: &Renderer) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called after a reloaded configuration was applied.
+    fn on_config_changed(&mut self, _renderer: &Renderer, _changes: &ConfigChanges) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// What `App::render` needs to record a frame.
+pub struct FrameContext<'a> {
+    pub renderer: &'a Renderer,
+    /// In `0..presenter::FRAMES_IN_FLIGHT`, to select the per-frame resources.
+    pub index: usize,
+    pub dynamic_state: DynamicState,
+    /// How far the frame is between the last two `App::fixed_update`, in `[0, 1)`.
+    pub alpha: f32,
+    // Only `None` while `record` runs
+    command_buffer_builder: Option<AutoCommandBufferBuilder>,
+}
+
+impl<'a> FrameContext<'a> {
+    /// Passes the command buffer of the frame through `f`, which can record draw
+    /// commands in the current subpass.
+    pub fn record<F>(&mut self, f: F) -> Result<()>
+    where
+        F: FnOnce(AutoCommandBufferBuilder) -> Result<AutoCommandBufferBuilder>,
+    {
+        let command_buffer_builder = self.command_buffer_builder.take().unwrap();
+        self.command_buffer_builder = Some(f(command_buffer_builder)?);
+        Ok(())
+    }
+}
+
+/// Opens a window and runs `app` until the window is closed.
+pub fn run<A: App>(config: RendererConfig, app: A) -> Result<()> {
+    run_with_watcher(config, None, app)
+}
+
+/// Like `run`, applying the configuration reloaded by `watcher` between frames.
+pub fn run_with_watcher<A: App>(
+    config: RendererConfig,
+    watcher: Option<ConfigWatcher>,
+    app: A,
+) -> Result<()> {
+    run_loop(config, watcher, None, app)
+}
+
+/// Like `run`, also rendering the frames of `recording` offscreen at the configured
+/// dimensions and writing them. The window closes once they are all written.
+pub fn record<A: App>(config: RendererConfig, recording: Recording, app: A) -> Result<()> {
+    run_loop(config, None, Some(recording), app)
+}
+
+/// Renders and writes the frames of `recording` without a window, at the configured
+/// dimensions. The format falls back to one that can be read back.
+pub fn record_headless<A: App>(
+    config: RendererConfig,
+    recording: Recording,
+    mut app: A,
+) -> Result<()> {
+    let mut renderer = Renderer::create_headless(config)?;
+
+    let format = select_readable_format(renderer.config.format);
+    let depth_format = renderer.select_depth_format()?;
+    let render_pass = create_render_pass(
+        renderer.device.clone(),
+        format,
+        depth_format,
+        renderer.config.msaa_samples,
+    )?;
+
+    let (width, height) = (renderer.config.width, renderer.config.height);
+    renderer.state.set_dimensions(width, height);
+
+    app.init(&mut renderer, render_pass.clone())?;
+
+    let target = OffscreenTarget::new(
+        renderer.device.clone(),
+        [width as u32, height as u32],
+        format,
+        depth_format,
+        renderer.config.msaa_samples,
+    )?;
+    let mut recorder = Recorder::new(&recording, target.dimensions())?;
+    let mut clock = Clock::new();
+    let mut timestep = FixedTimestep::new(renderer.config.update_rate);
+    let delta = Duration::from_secs_f32(1.0 / recording.fps);
+    // Every frame is waited for, none is ever in flight
+    let mut frames = FramesInFlight::new();
+
+    while recorder.frames_written() < recording.frames {
+        clock.advance(delta);
+        update(&mut app, &renderer.input, &mut clock, &mut timestep);
+
+        let image = render_offscreen(
+            &mut app,
+            &mut renderer,
+            &mut frames,
+            &target,
+            &render_pass,
+            timestep.alpha(),
+        )?;
+        recorder.write_frame(&image)?;
+        renderer.check_validation();
+    }
+
+    finish_recording(recorder, &recording)
+}
+
+fn run_loop<A: App>(
+    config: RendererConfig,
+    mut watcher: Option<ConfigWatcher>,
+    recording: Option<Recording>,
+    mut app: A,
+) -> Result<()> {
+    let mut renderer = Renderer::create(config)?;
+
+    let mut events_loop = EventsLoop::new();
+    let surface = WindowBuilder::new()
+        .with_dimensions(LogicalSize::from((
+            renderer.config.width as f64,
+            renderer.config.height as f64,
+        )))
+        .with_title(app.title())
+        .build_vk_surface(&events_loop, renderer.device.instance().clone())?;
+
+    let color_format = Presenter::select_format(&renderer, &surface)?;
+    let depth_format = renderer.select_depth_format()?;
+    let render_pass = create_render_pass(
+        renderer.device.clone(),
+        color_format,
+        depth_format,
+        renderer.config.msaa_samples,
+    )?;
+
+    app.init(&mut renderer, render_pass.clone())?;
+
+    let mut presenter = Presenter::new(&mut renderer, surface, render_pass.clone(), depth_format)?;
+    let mut fps_counter = FPSCounter::new();
+    let mut clock = Clock::new();
+    let mut timestep = FixedTimestep::new(renderer.config.update_rate);
+
+    // The recorded frames keep the configured dimensions, whatever the window size
+    let mut recording = match recording {
+        Some(recording) => {
+            let target = OffscreenTarget::new(
+                renderer.device.clone(),
+                [renderer.config.width as u32, renderer.config.height as u32],
+                color_format,
+                depth_format,
+                renderer.config.msaa_samples,
+            )?;
+            let recorder = Recorder::new(&recording, target.dimensions())?;
+            Some((recording, target, recorder))
+        }
+        None => None,
+    };
+
+    while renderer.state.is_running {
+        let frame_start = Instant::now();
+
+        if let Some(reload) = watcher.as_mut().and_then(ConfigWatcher::poll) {
+            match reload {
+                Ok(layered_config) => {
+                    let changes = renderer.apply_config(layered_config.config);
+                    if changes.window_size {
+                        presenter
+                            .surface()
+                            .window()
+                            .set_inner_size(LogicalSize::from((
+                                renderer.config.width as f64,
+                                renderer.config.height as f64,
+                            )));
+                    }
+                    if changes.update_rate {
+                        timestep.set_rate(renderer.config.update_rate);
+                    }
+                    app.on_config_changed(&renderer, &changes)?;
+                }
+                Err(e) => error!(
+                    "Failed to reload the configuration, keeping the previous one: {}",
+                    e
+                ),
+            }
+        }
+
+        events_loop.poll_events(|event| {
+            handle_event(&event, &mut renderer);
+            app.on_event(&event, &mut clock);
+        });
+
+        renderer.input.update();
+        match &recording {
+            Some((recording, ..)) => clock.advance(Duration::from_secs_f32(1.0 / recording.fps)),
+            None => clock.tick(),
+        };
+        update(&mut app, &renderer.input, &mut clock, &mut timestep);
+
+        if let Some(frame) = presenter.begin_frame(&mut renderer)? {
+            if frame.recreated {
+                app.on_recreated(&renderer)?;
+            }
+
+            let command_buffer = record_frame(
+                &mut app,
+                &renderer,
+                frame.framebuffer.clone(),
+                frame.index,
+                timestep.alpha(),
+            )?
+            .build()?;
+
+            presenter.end_frame(&mut renderer, frame, command_buffer)?;
+        }
+
+        // The swapchain images can't be copied, render the frame again to capture it
+        if let Some(path) = renderer.take_screenshot_request() {
+            let result = capture_offscreen(
+                &mut app,
+                &mut renderer,
+                &mut presenter,
+                &render_pass,
+                depth_format,
+                timestep.alpha(),
+                &path,
+            );
+            report_screenshot(&path, result);
+        }
+
+        // Recorded even when the window is minimized, to keep the recording in sync
+        // with the clock
+        if let Some((recording, target, recorder)) = &mut recording {
+            let image = render_offscreen(
+                &mut app,
+                &mut renderer,
+                presenter.frames_in_flight(),
+                target,
+                &render_pass,
+                timestep.alpha(),
+            )?;
+            recorder.write_frame(&image)?;
+            if recorder.frames_written() >= recording.frames {
+                renderer.state.is_running = false;
+            }
+        }
+
+        renderer.check_validation();
+
+        if let Some(mean_frame_duration) = fps_counter.update() {
+            presenter.surface().window().set_title(&format!(
+                "{} - {:.0} FPS",
+                app.title(),
+                1.0 / mean_frame_duration
+            ));
+        }
+
+        if let (Some(fps), None) = (renderer.config.fps, &recording) {
+            if let Some(sleep_duration) =
+                Duration::from_secs_f32(1.0 / fps).checked_sub(frame_start.elapsed())
+            {
+                std::thread::sleep(sleep_duration);
+            }
+        }
+    }
+
+    match recording {
+        Some((recording, _, recorder)) => finish_recording(recorder, &recording),
+        None => Ok(()),
+    }
+}
+
+/// Runs the updates of `app` for the time the clock just advanced by.
+fn update<A: App>(app: &mut A, input: &Input, clock: &mut Clock, timestep: &mut FixedTimestep) {
+    app.update(clock.smoothed_delta(), input, clock);
+
+    for _ in 0..timestep.advance(clock.delta()) {
+        app.fixed_update(timestep.step());
+    }
+}
+
+/// Records the render pass of a frame into `framebuffer`, letting `app` draw in it with
+/// the resources of `index`, which the GPU must be done with.
+pub fn record_frame<A: App>(
+    app: &mut A,
+    renderer: &Renderer,
+    framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    index: usize,
+    alpha: f32,
+) -> Result<AutoCommandBufferBuilder> {
+    let command_buffer_builder = AutoCommandBufferBuilder::primary_one_time_submit(
+        renderer.device.clone(),
+        renderer.graphics_queue.family(),
+    )?
+    .begin_render_pass(framebuffer, false, renderer.clear_values())?;
+
+    let mut context = FrameContext {
+        renderer,
+        index,
+        dynamic_state: renderer.state.dynamic_state(),
+        alpha,
+        command_buffer_builder: Some(command_buffer_builder),
+    };
+    app.render(&mut context)?;
+
+    Ok(context
+        .command_buffer_builder
+        .take()
+        .unwrap()
+        .end_render_pass()?)
+}
+
+/// Renders a frame into `target`, which must be compatible with `render_pass`, and
+/// reads it back. The frame uses the resources of index 0, so it first waits for the
+/// `frames` in flight to be done with them.
+pub fn render_offscreen<A: App>(
+    app: &mut A,
+    renderer: &mut Renderer,
+    frames: &mut FramesInFlight,
+    target: &OffscreenTarget,
+    render_pass: &Arc<dyn RenderPassAbstract + Send + Sync>,
+    alpha: f32,
+) -> Result<RgbaImage> {
+    frames.wait_idle()?;
+
+    // The viewport and projection must match the target for the time of the frame
+    let [window_width, window_height] = renderer.state.dimensions;
+    let [width, height] = target.dimensions();
+    renderer.state.set_dimensions(width as f32, height as f32);
+    let framebuffer = target.framebuffer(render_pass.clone())?;
+    let command_buffer_builder = record_frame(app, renderer, framebuffer, 0, alpha);
+    renderer.state.set_dimensions(window_width, window_height);
+
+    target
+        .copy_to_readback(command_buffer_builder?)?
+        .build()?
+        .execute(renderer.graphics_queue.clone())?
+        .then_signal_fence_and_flush()?
+        .wait(None)?;
+
+    target.read()
+}
+
+/// Renders a frame at the dimensions of the window and saves it to `path`, for
+/// swapchains whose images can't be copied.
+fn capture_offscreen<A: App>(
+    app: &mut A,
+    renderer: &mut Renderer,
+    presenter: &mut Presenter,
+    render_pass: &Arc<dyn RenderPassAbstract + Send + Sync>,
+    depth_format: Format,
+    alpha: f32,
+    path: &Path,
+) -> Result<()> {
+    let [width, height] = renderer.state.dimensions;
+    let target = OffscreenTarget::new(
+        renderer.device.clone(),
+        [width as u32, height as u32],
+        presenter.format(),
+        depth_format,
+        renderer.config.msaa_samples,
+    )?;
+
+    let frames = presenter.frames_in_flight();
+    let image = render_offscreen(app, renderer, frames, &target, render_pass, alpha)?;
+    Ok(image.save(path)?)
+}
+
+fn finish_recording(recorder: Recorder, recording: &Recording) -> Result<()> {
+    let frames = recorder.frames_written();
+    recorder.finish()?;
+    info!(
+        "{} frames written to {}",
+        frames,
+        recording.output.display()
+    );
+    Ok(())
+}
+
+/// Keeps the renderer state and the input up to date with the window. F12 takes a
+/// screenshot in the working directory.
+fn handle_event(event: &Event, renderer: &mut Renderer) {
+    let event = match event {
+        Event::WindowEvent { event, .. } => event,
+        _ => return,
+    };
+
+    let state = &mut renderer.state;
+    let input = &mut renderer.input;
+
+    match *event {
+        WindowEvent::CloseRequested => state.is_running = false,
+        WindowEvent::CursorMoved { position, .. } => input.new_mouse_position = Some(position),
+        WindowEvent::MouseInput {
+            state: button_state,
+            button: winit::MouseButton::Left,
+            ..
+        } => input.mouse_left_button_state = button_state,
+        WindowEvent::Resized(LogicalSize { width, height }) => {
+            state.set_dimensions(width as f32, height as f32);
+            state.need_recreation = true;
+        }
+        WindowEvent::KeyboardInput {
+            input:
+                KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(VirtualKeyCode::F12),
+                    ..
+                },
+            ..
+        } => renderer.capture_screenshot(screenshot_path()),
+        _ => {}
+    }
+}
+
+/// `screenshot-<milliseconds since the Unix epoch>.png`
+fn screenshot_path() -> PathBuf {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    PathBuf::from(format!("screenshot-{}.png", timestamp))
+}
@@ -0,0 +1,1005 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::{Deserialize, Serialize, Serializer};
+use toml::value::{Table, Value};
+use vulkano::format::{Format, FormatTy};
+use vulkano::swapchain::{ColorSpace, PresentMode};
+
+/// Prefix of the environment variables overriding configuration keys, e.g.
+/// `RENDERER_WIDTH=1920`.
+pub const ENV_PREFIX: &str = "RENDERER_";
+
+/// Configuration key selecting the configuration file itself. It can only be
+/// given on the command line (`--config`) or in the environment (`RENDERER_CONFIG`).
+pub const CONFIG_PATH_KEY: &str = "config";
+
+/// The formats that can be named in the configuration: the color formats a
+/// surface can present and the depth formats.
+#[derive(Deserialize)]
+#[serde(remote = "Format")]
+#[allow(non_camel_case_types)]
+pub enum FormatDef {
+    R4G4B4A4UnormPack16,
+    B4G4R4A4UnormPack16,
+    R5G6B5UnormPack16,
+    B5G6R5UnormPack16,
+    R5G5B5A1UnormPack16,
+    B5G5R5A1UnormPack16,
+    A1R5G5B5UnormPack16,
+    R8G8B8A8Unorm,
+    R8G8B8A8Srgb,
+    B8G8R8A8Unorm,
+    B8G8R8A8Srgb,
+    A8B8G8R8UnormPack32,
+    A8B8G8R8SrgbPack32,
+    A2R10G10B10UnormPack32,
+    A2B10G10R10UnormPack32,
+    R16G16B16A16Unorm,
+    R16G16B16A16Sfloat,
+    B10G11R11UfloatPack32,
+
+    D16Unorm,
+    X8_D24UnormPack32,
+    D32Sfloat,
+    D16Unorm_S8Uint,
+    D24Unorm_S8Uint,
+    D32Sfloat_S8Uint,
+}
+
+impl From<FormatDef> for Format {
+    fn from(def: FormatDef) -> Format {
+        match def {
+            FormatDef::R4G4B4A4UnormPack16 => Format::R4G4B4A4UnormPack16,
+            FormatDef::B4G4R4A4UnormPack16 => Format::B4G4R4A4UnormPack16,
+            FormatDef::R5G6B5UnormPack16 => Format::R5G6B5UnormPack16,
+            FormatDef::B5G6R5UnormPack16 => Format::B5G6R5UnormPack16,
+            FormatDef::R5G5B5A1UnormPack16 => Format::R5G5B5A1UnormPack16,
+            FormatDef::B5G5R5A1UnormPack16 => Format::B5G5R5A1UnormPack16,
+            FormatDef::A1R5G5B5UnormPack16 => Format::A1R5G5B5UnormPack16,
+            FormatDef::R8G8B8A8Unorm => Format::R8G8B8A8Unorm,
+            FormatDef::R8G8B8A8Srgb => Format::R8G8B8A8Srgb,
+            FormatDef::B8G8R8A8Unorm => Format::B8G8R8A8Unorm,
+            FormatDef::B8G8R8A8Srgb => Format::B8G8R8A8Srgb,
+            FormatDef::A8B8G8R8UnormPack32 => Format::A8B8G8R8UnormPack32,
+            FormatDef::A8B8G8R8SrgbPack32 => Format::A8B8G8R8SrgbPack32,
+            FormatDef::A2R10G10B10UnormPack32 => Format::A2R10G10B10UnormPack32,
+            FormatDef::A2B10G10R10UnormPack32 => Format::A2B10G10R10UnormPack32,
+            FormatDef::R16G16B16A16Unorm => Format::R16G16B16A16Unorm,
+            FormatDef::R16G16B16A16Sfloat => Format::R16G16B16A16Sfloat,
+            FormatDef::B10G11R11UfloatPack32 => Format::B10G11R11UfloatPack32,
+            FormatDef::D16Unorm => Format::D16Unorm,
+            FormatDef::X8_D24UnormPack32 => Format::X8_D24UnormPack32,
+            FormatDef::D32Sfloat => Format::D32Sfloat,
+            FormatDef::D16Unorm_S8Uint => Format::D16Unorm_S8Uint,
+            FormatDef::D24Unorm_S8Uint => Format::D24Unorm_S8Uint,
+            FormatDef::D32Sfloat_S8Uint => Format::D32Sfloat_S8Uint,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(remote = "ColorSpace")]
+pub enum ColorSpaceDef {
+    SrgbNonLinear,
+    DisplayP3NonLinear,
+    ExtendedSrgbLinear,
+    DciP3Linear,
+    DciP3NonLinear,
+    Bt709Linear,
+    Bt709NonLinear,
+    Bt2020Linear,
+    Hdr10St2084,
+    DolbyVision,
+    Hdr10Hlg,
+    AdobeRgbLinear,
+    AdobeRgbNonLinear,
+    PassThrough,
+}
+
+impl From<ColorSpaceDef> for ColorSpace {
+    fn from(def: ColorSpaceDef) -> ColorSpace {
+        match def {
+            ColorSpaceDef::SrgbNonLinear => ColorSpace::SrgbNonLinear,
+            ColorSpaceDef::DisplayP3NonLinear => ColorSpace::DisplayP3NonLinear,
+            ColorSpaceDef::ExtendedSrgbLinear => ColorSpace::ExtendedSrgbLinear,
+            ColorSpaceDef::DciP3Linear => ColorSpace::DciP3Linear,
+            ColorSpaceDef::DciP3NonLinear => ColorSpace::DciP3NonLinear,
+            ColorSpaceDef::Bt709Linear => ColorSpace::Bt709Linear,
+            ColorSpaceDef::Bt709NonLinear => ColorSpace::Bt709NonLinear,
+            ColorSpaceDef::Bt2020Linear => ColorSpace::Bt2020Linear,
+            ColorSpaceDef::Hdr10St2084 => ColorSpace::Hdr10St2084,
+            ColorSpaceDef::DolbyVision => ColorSpace::DolbyVision,
+            ColorSpaceDef::Hdr10Hlg => ColorSpace::Hdr10Hlg,
+            ColorSpaceDef::AdobeRgbLinear => ColorSpace::AdobeRgbLinear,
+            ColorSpaceDef::AdobeRgbNonLinear => ColorSpace::AdobeRgbNonLinear,
+            ColorSpaceDef::PassThrough => ColorSpace::PassThrough,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "PresentMode", rename_all = "snake_case")]
+pub enum PresentModeDef {
+    Immediate,
+    Mailbox,
+    Fifo,
+    #[serde(rename = "fifo_relaxed")]
+    Relaxed,
+}
+
+impl From<PresentModeDef> for PresentMode {
+    fn from(def: PresentModeDef) -> PresentMode {
+        match def {
+            PresentModeDef::Immediate => PresentMode::Immediate,
+            PresentModeDef::Mailbox => PresentMode::Mailbox,
+            PresentModeDef::Fifo => PresentMode::Fifo,
+            PresentModeDef::Relaxed => PresentMode::Relaxed,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RendererConfig {
+    pub fps: Option<f32>,
+    /// Fixed updates per second of `App::fixed_update`, independent of the frame rate.
+    pub update_rate: f32,
+    pub width: f32,
+    pub height: f32,
+    pub line_width: f32,
+    pub clear_color: [f32; 3],
+    #[serde(deserialize_with = "FormatDef::deserialize", serialize_with = "serialize_debug")]
+    pub format: vulkano::format::Format,
+    #[serde(deserialize_with = "FormatDef::deserialize", serialize_with = "serialize_debug")]
+    pub depth_format: vulkano::format::Format,
+    /// Only `SrgbNonLinear` for now, the color space vulkano creates swapchains in.
+    #[serde(
+        deserialize_with = "ColorSpaceDef::deserialize",
+        serialize_with = "serialize_debug"
+    )]
+    pub color_space: ColorSpace,
+    /// `immediate` or `mailbox` along with no `fps` gives an uncapped frame rate.
+    #[serde(with = "PresentModeDef")]
+    pub present_mode: PresentMode,
+    /// Number of swapchain images, clamped to what the surface supports. Defaults to
+    /// the surface minimum.
+    pub image_count: Option<u32>,
+    /// Samples per pixel of the color and depth attachments, resolved before
+    /// presenting. Lowered to what the device supports; 1 disables MSAA.
+    pub msaa_samples: u32,
+    /// Forces the physical device, by name substring or UUID. The best ranked
+    /// device is used when unset.
+    pub device: Option<String>,
+    /// Enables the Khronos validation layer, when installed, and forwards its
+    /// messages to `log`. On by default in debug builds.
+    pub validation: bool,
+    /// Makes `Renderer::check_validation` panic when the validation layer reported
+    /// an error. Meant for tests.
+    pub panic_on_validation_error: bool,
+}
+
+/// Formats and color spaces are written back with the same names `FormatDef` and
+/// `ColorSpaceDef` read, which are the vulkano variant names.
+fn serialize_debug<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: fmt::Debug,
+    S: Serializer,
+{
+    serializer.serialize_str(&format!("{:?}", value))
+}
+
+impl Default for RendererConfig {
+    fn default() -> Self {
+        Self {
+            fps: None,
+            update_rate: 60.0,
+            width: 1280.0,
+            height: 720.0,
+            line_width: 1.0,
+            clear_color: [0.0, 0.0, 0.0],
+            format: Format::B8G8R8A8Unorm,
+            depth_format: Format::D16Unorm,
+            color_space: ColorSpace::SrgbNonLinear,
+            present_mode: PresentMode::Fifo,
+            image_count: None,
+            msaa_samples: 1,
+            device: None,
+            validation: cfg!(debug_assertions),
+            panic_on_validation_error: false,
+        }
+    }
+}
+
+impl RendererConfig {
+    /// Every key that can be set from a file, the environment or the command line.
+    pub const KEYS: &'static [&'static str] = &[
+        "fps",
+        "update_rate",
+        "width",
+        "height",
+        "line_width",
+        "clear_color",
+        "format",
+        "depth_format",
+        "color_space",
+        "present_mode",
+        "image_count",
+        "msaa_samples",
+        "device",
+        "validation",
+        "panic_on_validation_error",
+    ];
+
+    /// Loads the configuration from a TOML file. Keys missing from the file keep
+    /// their default value.
+    pub fn load_from_file<P: AsRef<Path>>(p: P) -> Result<Self, ConfigError> {
+        let mut config_file = File::open(p)?;
+
+        let mut config_string = String::new();
+        config_file.read_to_string(&mut config_string)?;
+
+        Self::load_from_str(config_string.as_str())
+    }
+
+    pub fn load_from_str(s: &str) -> Result<Self, ConfigError> {
+        let config: Self = toml::from_str(s)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks the values that can be parsed but make no sense for the renderer.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        use ConfigError::InvalidValue;
+
+        if let Some(fps) = self.fps {
+            if !fps.is_finite() || fps <= 0.0 {
+                return Err(InvalidValue {
+                    key: "fps",
+                    reason: format!("must be strictly positive, got {}", fps),
+                });
+            }
+        }
+
+        if !self.update_rate.is_finite() || self.update_rate <= 0.0 {
+            return Err(InvalidValue {
+                key: "update_rate",
+                reason: format!("must be strictly positive, got {}", self.update_rate),
+            });
+        }
+
+        if !self.width.is_finite() || self.width < 1.0 {
+            return Err(InvalidValue {
+                key: "width",
+                reason: format!("must be at least 1, got {}", self.width),
+            });
+        }
+
+        if !self.height.is_finite() || self.height < 1.0 {
+            return Err(InvalidValue {
+                key: "height",
+                reason: format!("must be at least 1, got {}", self.height),
+            });
+        }
+
+        if !self.line_width.is_finite() || self.line_width <= 0.0 {
+            return Err(InvalidValue {
+                key: "line_width",
+                reason: format!("must be strictly positive, got {}", self.line_width),
+            });
+        }
+
+        if self.clear_color.iter().any(|c| !(0.0..=1.0).contains(c)) {
+            return Err(InvalidValue {
+                key: "clear_color",
+                reason: format!(
+                    "components must be in [0, 1], got {:?}",
+                    self.clear_color
+                ),
+            });
+        }
+
+        if self.image_count == Some(0) {
+            return Err(InvalidValue {
+                key: "image_count",
+                reason: String::from("must be at least 1"),
+            });
+        }
+
+        if !self.msaa_samples.is_power_of_two() || self.msaa_samples > 64 {
+            return Err(InvalidValue {
+                key: "msaa_samples",
+                reason: format!(
+                    "must be a power of two between 1 and 64, got {}",
+                    self.msaa_samples
+                ),
+            });
+        }
+
+        // vulkano creates every swapchain in the sRGB color space
+        if self.color_space != ColorSpace::SrgbNonLinear {
+            return Err(InvalidValue {
+                key: "color_space",
+                reason: format!(
+                    "only SrgbNonLinear is supported by swapchains, got {:?}",
+                    self.color_space
+                ),
+            });
+        }
+
+        if !is_color_format(self.format) {
+            return Err(InvalidValue {
+                key: "format",
+                reason: format!("{:?} is not a color format", self.format),
+            });
+        }
+
+        if !is_depth_format(self.depth_format) {
+            return Err(InvalidValue {
+                key: "depth_format",
+                reason: format!("{:?} is not a depth format", self.depth_format),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+fn is_color_format(format: Format) -> bool {
+    matches!(format.ty(), FormatTy::Float | FormatTy::Uint | FormatTy::Sint)
+}
+
+fn is_depth_format(format: Format) -> bool {
+    matches!(format.ty(), FormatTy::Depth | FormatTy::DepthStencil)
+}
+
+/// Where the value of a configuration key comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigSource {
+    Default,
+    File(PathBuf),
+    Environment(String),
+    CommandLine(String),
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigSource::Default => write!(f, "default"),
+            ConfigSource::File(path) => write!(f, "file {}", path.display()),
+            ConfigSource::Environment(var) => write!(f, "environment {}", var),
+            ConfigSource::CommandLine(flag) => write!(f, "command line {}", flag),
+        }
+    }
+}
+
+/// Builds a `RendererConfig` from layers, each one overriding the previous:
+/// compiled defaults, the configuration file, `RENDERER_*` environment variables
+/// and finally command-line flags.
+#[derive(Debug, Clone)]
+pub struct RendererConfigBuilder {
+    file: Option<PathBuf>,
+    file_required: bool,
+    use_env: bool,
+    args: Vec<(String, String)>,
+}
+
+impl RendererConfigBuilder {
+    pub fn start() -> Self {
+        Self {
+            file: None,
+            file_required: false,
+            use_env: false,
+            args: Vec::new(),
+        }
+    }
+
+    /// Reads the given file, failing if it does not exist.
+    pub fn with_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.file = Some(path.into());
+        self.file_required = true;
+        self
+    }
+
+    /// Reads the given file if it exists.
+    pub fn with_optional_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.file = Some(path.into());
+        self.file_required = false;
+        self
+    }
+
+    pub fn with_env(mut self) -> Self {
+        self.use_env = true;
+        self
+    }
+
+    /// Parses `--key value` and `--key=value` flags. Dashes in keys are read as
+    /// underscores and a flag without a value is read as `true`.
+    pub fn with_args<I, S>(mut self, args: I) -> Result<Self, ConfigError>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut args = args.into_iter().map(Into::into).peekable();
+
+        while let Some(arg) = args.next() {
+            let flag = match arg.strip_prefix("--") {
+                Some(flag) => flag,
+                None => return Err(ConfigError::UnknownArgument(arg)),
+            };
+
+            let (key, value) = match flag.find('=') {
+                Some(index) => (&flag[..index], flag[index + 1..].to_string()),
+                None => match args.peek() {
+                    Some(next) if !next.starts_with("--") => (flag, args.next().unwrap()),
+                    _ => (flag, String::from("true")),
+                },
+            };
+
+            let key = key.replace('-', "_");
+            if key != CONFIG_PATH_KEY && !RendererConfig::KEYS.contains(&key.as_str()) {
+                return Err(ConfigError::UnknownArgument(arg));
+            }
+
+            self.args.push((key, value));
+        }
+
+        Ok(self)
+    }
+
+    /// The configuration file to read, and whether it must exist. `--config` wins
+    /// over `RENDERER_CONFIG`, which wins over the file given to the builder.
+    pub fn config_file(&self) -> Option<(PathBuf, bool)> {
+        let env_path = if self.use_env {
+            env::var_os(format!("{}{}", ENV_PREFIX, CONFIG_PATH_KEY.to_uppercase()))
+        } else {
+            None
+        };
+
+        self.args
+            .iter()
+            .rev()
+            .find(|(key, _)| key == CONFIG_PATH_KEY)
+            .map(|(_, path)| (PathBuf::from(path), true))
+            .or_else(|| env_path.map(|path| (PathBuf::from(path), true)))
+            .or_else(|| self.file.clone().map(|path| (path, self.file_required)))
+    }
+
+    pub fn build(&self) -> Result<LayeredConfig, ConfigError> {
+        let mut table = match Value::try_from(RendererConfig::default()) {
+            Ok(Value::Table(table)) => table,
+            _ => unreachable!("RendererConfig serializes to a table"),
+        };
+        let mut sources: BTreeMap<String, ConfigSource> = RendererConfig::KEYS
+            .iter()
+            .map(|key| (key.to_string(), ConfigSource::Default))
+            .collect();
+
+        let env_overrides: Vec<(String, String, String)> = if self.use_env {
+            env::vars()
+                .filter_map(|(var, value)| {
+                    let key = var.strip_prefix(ENV_PREFIX)?.to_lowercase();
+                    Some((key, var, value))
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let file = self.config_file();
+
+        if let Some((path, required)) = file {
+            if required || path.exists() {
+                let mut config_file = File::open(&path)?;
+                let mut config_string = String::new();
+                config_file.read_to_string(&mut config_string)?;
+
+                let file_table: Table = toml::from_str(config_string.as_str())?;
+                for (key, value) in file_table {
+                    if !RendererConfig::KEYS.contains(&key.as_str()) {
+                        return Err(ConfigError::UnknownKey { key, path });
+                    }
+                    sources.insert(key.clone(), ConfigSource::File(path.clone()));
+                    table.insert(key, value);
+                }
+            }
+        }
+
+        for (key, var, value) in env_overrides {
+            if RendererConfig::KEYS.contains(&key.as_str()) {
+                table.insert(key.clone(), parse_value(&key, &value)?);
+                sources.insert(key, ConfigSource::Environment(var));
+            }
+        }
+
+        for (key, value) in self.args.iter() {
+            if key != CONFIG_PATH_KEY {
+                table.insert(key.clone(), parse_value(key, value)?);
+                let flag = format!("--{}", key.replace('_', "-"));
+                sources.insert(key.clone(), ConfigSource::CommandLine(flag));
+            }
+        }
+
+        let config: RendererConfig = Value::Table(table).try_into()?;
+        config.validate()?;
+
+        Ok(LayeredConfig { config, sources })
+    }
+}
+
+/// The TOML type of the value of a key.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ValueType {
+    Float,
+    Integer,
+    Boolean,
+    String,
+    FloatArray,
+}
+
+impl ValueType {
+    fn of(key: &str) -> Option<Self> {
+        match key {
+            "fps" | "update_rate" | "width" | "height" | "line_width" => Some(ValueType::Float),
+            "image_count" | "msaa_samples" => Some(ValueType::Integer),
+            "validation" | "panic_on_validation_error" => Some(ValueType::Boolean),
+            "format" | "depth_format" | "color_space" | "present_mode" | "device" => {
+                Some(ValueType::String)
+            }
+            "clear_color" => Some(ValueType::FloatArray),
+            _ => None,
+        }
+    }
+}
+
+/// Reads a value given as a string in the environment or on the command line, with
+/// the type of its key, so that `--format B8G8R8A8Srgb` needs no quoting and
+/// `--device 1080` stays a device name.
+fn parse_value(key: &str, raw: &str) -> Result<Value, ConfigError> {
+    let invalid = |expected: &str| {
+        let key = RendererConfig::KEYS
+            .iter()
+            .find(|k| **k == key)
+            .copied()
+            .expect("values are only parsed for known keys");
+        ConfigError::InvalidValue {
+            key,
+            reason: format!("expected {}, got `{}`", expected, raw),
+        }
+    };
+
+    let raw_value = || {
+        toml::from_str::<Table>(&format!("value = {}", raw))
+            .ok()
+            .and_then(|mut table| table.remove("value"))
+    };
+
+    match ValueType::of(key).expect("values are only parsed for known keys") {
+        ValueType::Float => raw
+            .trim()
+            .parse::<f64>()
+            .map(Value::Float)
+            .map_err(|_| invalid("a number")),
+        ValueType::Integer => raw
+            .trim()
+            .parse::<i64>()
+            .map(Value::Integer)
+            .map_err(|_| invalid("an integer")),
+        ValueType::Boolean => raw
+            .trim()
+            .parse::<bool>()
+            .map(Value::Boolean)
+            .map_err(|_| invalid("`true` or `false`")),
+        ValueType::String => Ok(Value::String(raw.to_string())),
+        ValueType::FloatArray => match raw_value() {
+            Some(Value::Array(values))
+                if values.iter().all(|v| v.is_float() || v.is_integer()) =>
+            {
+                Ok(Value::Array(values))
+            }
+            _ => Err(invalid("an array of numbers such as `[0.1, 0.2, 0.3]`")),
+        },
+    }
+}
+
+/// A configuration along with the source that set each of its keys.
+#[derive(Debug)]
+pub struct LayeredConfig {
+    pub config: RendererConfig,
+    pub sources: BTreeMap<String, ConfigSource>,
+}
+
+impl LayeredConfig {
+    pub fn source(&self, key: &str) -> Option<&ConfigSource> {
+        self.sources.get(key)
+    }
+}
+
+impl fmt::Display for LayeredConfig {
+    /// Prints the configuration as TOML, with the source of each key as a comment.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let table = match Value::try_from(&self.config) {
+            Ok(Value::Table(table)) => table,
+            _ => return Err(fmt::Error),
+        };
+
+        for key in RendererConfig::KEYS {
+            let source = self.sources.get(*key).unwrap_or(&ConfigSource::Default);
+            match table.get(*key) {
+                Some(value) => writeln!(f, "{} = {} # {}", key, shortest_f32(value), source)?,
+                None => writeln!(f, "# {} is not set # {}", key, source)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Config values are stored as `f32`, so print floats with the shortest
+/// representation that reads back as the same `f32` instead of its `f64` widening.
+fn shortest_f32(value: &Value) -> Value {
+    match value {
+        Value::Float(x) => Value::Float((*x as f32).to_string().parse().unwrap_or(*x)),
+        Value::Array(values) => Value::Array(values.iter().map(shortest_f32).collect()),
+        other => other.clone(),
+    }
+}
+
+/// Watches the configuration file and rebuilds the configuration, with the same
+/// layers, when the file is modified.
+pub struct ConfigWatcher {
+    builder: RendererConfigBuilder,
+    last_modified: Option<SystemTime>,
+    last_check: Instant,
+    interval: Duration,
+}
+
+impl ConfigWatcher {
+    pub fn new(builder: RendererConfigBuilder) -> Self {
+        let last_modified = builder
+            .config_file()
+            .and_then(|(path, _)| modification_time(&path));
+
+        Self {
+            builder,
+            last_modified,
+            last_check: Instant::now(),
+            interval: Duration::from_millis(500),
+        }
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Checks the file at most once per interval. Returns the rebuilt configuration
+    /// when the file changed since the last reload attempt. A file that is removed
+    /// is ignored, so the current configuration is kept.
+    pub fn poll(&mut self) -> Option<Result<LayeredConfig, ConfigError>> {
+        let now = Instant::now();
+        if now - self.last_check < self.interval {
+            return None;
+        }
+        self.last_check = now;
+
+        let modified = self
+            .builder
+            .config_file()
+            .and_then(|(path, _)| modification_time(&path))?;
+
+        if self.last_modified == Some(modified) {
+            return None;
+        }
+        self.last_modified = Some(modified);
+
+        Some(self.builder.build())
+    }
+}
+
+fn modification_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// The keys that differ between two configurations.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ConfigChanges {
+    pub fps: bool,
+    pub update_rate: bool,
+    pub window_size: bool,
+    pub line_width: bool,
+    pub clear_color: bool,
+    /// The present mode or the number of swapchain images changed.
+    pub swapchain: bool,
+    /// The color format, depth format or color space changed.
+    pub format: bool,
+    pub msaa_samples: bool,
+    pub device: bool,
+    pub validation: bool,
+}
+
+impl ConfigChanges {
+    pub fn between(old: &RendererConfig, new: &RendererConfig) -> Self {
+        Self {
+            fps: old.fps != new.fps,
+            update_rate: old.update_rate != new.update_rate,
+            window_size: old.width != new.width || old.height != new.height,
+            line_width: old.line_width != new.line_width,
+            clear_color: old.clear_color != new.clear_color,
+            swapchain: old.present_mode != new.present_mode || old.image_count != new.image_count,
+            format: old.format != new.format
+                || old.depth_format != new.depth_format
+                || old.color_space != new.color_space,
+            msaa_samples: old.msaa_samples != new.msaa_samples,
+            device: old.device != new.device,
+            validation: old.validation != new.validation
+                || old.panic_on_validation_error != new.panic_on_validation_error,
+        }
+    }
+
+    /// Whether the swapchain, and the pipelines recorded against it, must be rebuilt
+    /// for the changes to take effect.
+    pub fn need_recreation(&self) -> bool {
+        self.swapchain || self.line_width
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    IoError(std::io::Error),
+    /// The file is not valid TOML, or a value has the wrong type. `line` and
+    /// `column` are 1-based when known.
+    ParseError {
+        message: String,
+        line: Option<usize>,
+        column: Option<usize>,
+    },
+    InvalidValue {
+        key: &'static str,
+        reason: String,
+    },
+    UnknownArgument(String),
+    /// The configuration file sets a key that does not exist.
+    UnknownKey { key: String, path: PathBuf },
+}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(error: std::io::Error) -> Self {
+        ConfigError::IoError(error)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(error: toml::de::Error) -> Self {
+        let (line, column) = match error.line_col() {
+            Some((line, column)) => (Some(line + 1), Some(column + 1)),
+            None => (None, None),
+        };
+
+        ConfigError::ParseError {
+            message: error.to_string(),
+            line,
+            column,
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::IoError(e) => write!(f, "failed to read configuration: {}", e),
+            ConfigError::ParseError { message, .. } => {
+                write!(f, "invalid configuration: {}", message)
+            }
+            ConfigError::InvalidValue { key, reason } => {
+                write!(f, "invalid value for `{}`: {}", key, reason)
+            }
+            ConfigError::UnknownArgument(arg) => write!(f, "unknown argument `{}`", arg),
+            ConfigError::UnknownKey { key, path } => {
+                write!(f, "unknown key `{}` in {}", key, path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `contents` to a configuration file of its own in the temporary directory.
+    fn config_file(name: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("renderer-{}-{}.toml", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn build(args: &[&str]) -> Result<LayeredConfig, ConfigError> {
+        RendererConfigBuilder::start()
+            .with_args(args.iter().cloned())?
+            .build()
+    }
+
+    #[test]
+    fn defaults_without_layers() {
+        let layered = build(&[]).unwrap();
+
+        assert_eq!(layered.config.width, 1280.0);
+        assert_eq!(layered.config.device, None);
+        assert_eq!(layered.source("width"), Some(&ConfigSource::Default));
+    }
+
+    #[test]
+    fn layers_override_each_other_in_order() {
+        let path = config_file(
+            "layers",
+            "width = 800.0\nheight = 600.0\nline_width = 2.0\nfps = 30.0\n",
+        );
+        env::set_var("RENDERER_HEIGHT", "700");
+        env::set_var("RENDERER_LINE_WIDTH", "3");
+
+        let layered = RendererConfigBuilder::start()
+            .with_file(&path)
+            .with_env()
+            .with_args(vec!["--line-width", "4"])
+            .unwrap()
+            .build();
+        env::remove_var("RENDERER_HEIGHT");
+        env::remove_var("RENDERER_LINE_WIDTH");
+        let layered = layered.unwrap();
+
+        let config = &layered.config;
+        assert_eq!(config.fps, Some(30.0));
+        assert_eq!(config.width, 800.0);
+        assert_eq!(config.height, 700.0);
+        assert_eq!(config.line_width, 4.0);
+        assert_eq!(config.update_rate, 60.0);
+
+        assert_eq!(layered.source("width"), Some(&ConfigSource::File(path)));
+        assert_eq!(
+            layered.source("height"),
+            Some(&ConfigSource::Environment(String::from("RENDERER_HEIGHT")))
+        );
+        assert_eq!(
+            layered.source("line_width"),
+            Some(&ConfigSource::CommandLine(String::from("--line-width")))
+        );
+        assert_eq!(layered.source("update_rate"), Some(&ConfigSource::Default));
+    }
+
+    #[test]
+    fn config_flag_wins_over_builder_file() {
+        let ignored = config_file("ignored", "width = 100.0\n");
+        let used = config_file("used", "width = 200.0\n");
+
+        let builder = RendererConfigBuilder::start()
+            .with_file(&ignored)
+            .with_args(vec![format!("--config={}", used.display())])
+            .unwrap();
+
+        assert_eq!(builder.config_file(), Some((used, true)));
+        assert_eq!(builder.build().unwrap().config.width, 200.0);
+    }
+
+    #[test]
+    fn missing_optional_file_is_ignored() {
+        let path = env::temp_dir().join("renderer-does-not-exist.toml");
+
+        let optional = RendererConfigBuilder::start().with_optional_file(&path);
+        assert!(optional.build().is_ok());
+
+        let required = RendererConfigBuilder::start().with_file(&path);
+        assert!(matches!(required.build(), Err(ConfigError::IoError(_))));
+    }
+
+    #[test]
+    fn values_are_read_with_the_type_of_their_key() {
+        let config = build(&[
+            "--device",
+            "1080",
+            "--width=1920",
+            "--msaa-samples",
+            "4",
+            "--validation",
+            "--clear-color",
+            "[0.5, 0, 1]",
+            "--format",
+            "B8G8R8A8Srgb",
+            "--present-mode",
+            "mailbox",
+        ])
+        .unwrap()
+        .config;
+
+        assert_eq!(config.device.as_deref(), Some("1080"));
+        assert_eq!(config.width, 1920.0);
+        assert_eq!(config.msaa_samples, 4);
+        assert!(config.validation);
+        assert_eq!(config.clear_color, [0.5, 0.0, 1.0]);
+        assert_eq!(config.format, Format::B8G8R8A8Srgb);
+        assert_eq!(config.present_mode, PresentMode::Mailbox);
+    }
+
+    #[test]
+    fn bad_values_are_rejected() {
+        for (args, bad_key) in &[
+            (&["--width", "abc"][..], "width"),
+            (&["--msaa-samples", "2.5"][..], "msaa_samples"),
+            (&["--validation", "yes"][..], "validation"),
+            (&["--clear-color", "red"][..], "clear_color"),
+            (&["--clear-color", "[\"red\"]"][..], "clear_color"),
+        ] {
+            assert_eq!(invalid_key(build(args)), *bad_key, "{:?}", args);
+        }
+
+        // Parsed, but of a type the key can't hold
+        assert!(matches!(
+            build(&["--format", "NotAFormat"]),
+            Err(ConfigError::ParseError { .. })
+        ));
+    }
+
+    fn invalid_key<T: fmt::Debug>(result: Result<T, ConfigError>) -> &'static str {
+        match result {
+            Err(ConfigError::InvalidValue { key, .. }) => key,
+            other => panic!("expected an invalid value, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn negative_line_width_is_rejected() {
+        assert_eq!(invalid_key(build(&["--line-width", "-1"])), "line_width");
+        assert_eq!(
+            invalid_key(RendererConfig::load_from_str("line_width = -1.0")),
+            "line_width"
+        );
+    }
+
+    #[test]
+    fn unknown_arguments_are_rejected() {
+        for args in &[&["--widht", "3"][..], &["width"][..]] {
+            match RendererConfigBuilder::start().with_args(args.iter().cloned()) {
+                Err(ConfigError::UnknownArgument(_)) => {}
+                other => panic!("{:?} should be unknown, got {:?}", args, other),
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_file_keys_are_rejected() {
+        let path = config_file("unknown", "width = 100.0\nwidht = 3.0\n");
+
+        match RendererConfigBuilder::start().with_file(&path).build() {
+            Err(ConfigError::UnknownKey {
+                key,
+                path: error_path,
+            }) => {
+                assert_eq!(key, "widht");
+                assert_eq!(error_path, path);
+            }
+            other => panic!("expected an unknown key, got {:?}", other),
+        }
+
+        assert!(matches!(
+            RendererConfig::load_from_str("widht = 3.0"),
+            Err(ConfigError::ParseError { .. })
+        ));
+    }
+
+    #[test]
+    fn parse_errors_have_a_position() {
+        match RendererConfig::load_from_str("width = 1.0\nheight = \n") {
+            Err(ConfigError::ParseError { line, column, .. }) => {
+                assert_eq!(line, Some(2));
+                assert!(column.is_some());
+            }
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+}
@@ -1,19 +1,349 @@
-use std::slice;
-use vulkano::instance::PhysicalDevice;
-
-pub fn print_infos(device: &PhysicalDevice) {
-    println!("Device: {}", device.name());
-    println!("API Version: {}", device.api_version());
-    println!("Driver Version: {}", device.driver_version());
-    println!("PCI Device ID: {}", device.pci_device_id());
-    println!("PCI Vendor ID: {}", device.pci_vendor_id());
-    println!("UUID: {}", uuid_to_string(device.uuid()));
-}
-
-fn uuid_to_string(bytes: &[u8; 16]) -> String {
-    let uuid = unsafe {
-        let ptr = bytes.as_ptr() as *const u32;
-        slice::from_raw_parts(ptr, 4)
-    };
-    format!("{}-{}-{}-{}", uuid[0], uuid[1], uuid[2], uuid[3])
+use log::info;
+use serde::Serialize;
+use vulkano::device::RawDeviceExtensions;
+use vulkano::instance::{Limits, PhysicalDevice};
+use vulkano::swapchain::Surface;
+
+pub fn log_infos(device: &PhysicalDevice) {
+    info!("Device: {}", device.name());
+    info!("API Version: {}", device.api_version());
+    info!("Driver Version: {}", device.driver_version());
+    info!("PCI Device ID: {}", device.pci_device_id());
+    info!("PCI Vendor ID: {}", device.pci_vendor_id());
+    info!("UUID: {}", uuid_to_string(device.uuid()));
+}
+
+/// Formats a UUID in the standard 8-4-4-4-12 hexadecimal form.
+pub fn uuid_to_string(bytes: &[u8; 16]) -> String {
+    let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        hex[0..4].concat(),
+        hex[4..6].concat(),
+        hex[6..8].concat(),
+        hex[8..10].concat(),
+        hex[10..16].concat()
+    )
+}
+
+/// Everything we know about a physical device, meant to be attached to bug reports.
+#[derive(Debug, Serialize)]
+pub struct DeviceReport {
+    pub name: String,
+    pub device_type: String,
+    pub api_version: String,
+    pub driver_version: u32,
+    pub pci_vendor_id: u32,
+    pub pci_device_id: u32,
+    pub uuid: String,
+    pub limits: LimitsReport,
+    pub memory_heaps: Vec<MemoryHeapReport>,
+    pub memory_types: Vec<MemoryTypeReport>,
+    pub queue_families: Vec<QueueFamilyReport>,
+    pub features: Vec<String>,
+    pub extensions: Vec<String>,
+    pub surface: Option<SurfaceReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MemoryHeapReport {
+    pub id: u32,
+    pub size: usize,
+    pub device_local: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MemoryTypeReport {
+    pub id: u32,
+    pub heap: u32,
+    pub device_local: bool,
+    pub host_visible: bool,
+    pub host_coherent: bool,
+    pub host_cached: bool,
+    pub lazily_allocated: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct QueueFamilyReport {
+    pub id: u32,
+    pub queues_count: usize,
+    pub graphics: bool,
+    pub compute: bool,
+    pub transfer: bool,
+    pub sparse_binding: bool,
+    pub timestamp_valid_bits: Option<u32>,
+    pub min_image_transfer_granularity: [u32; 3],
+}
+
+/// What the device supports when presenting to a given surface.
+#[derive(Debug, Serialize)]
+pub struct SurfaceReport {
+    pub min_image_count: u32,
+    pub max_image_count: Option<u32>,
+    pub current_extent: Option<[u32; 2]>,
+    pub formats: Vec<SurfaceFormatReport>,
+    pub present_modes: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SurfaceFormatReport {
+    pub format: String,
+    pub color_space: String,
+}
+
+/// Declares `LimitsReport` with one field per device limit, the same way vulkano
+/// declares `Limits`.
+macro_rules! limits_report {
+    ($($name:ident: $t:ty,)*) => (
+        #[derive(Debug, Serialize)]
+        pub struct LimitsReport {
+            $(pub $name: $t,)*
+        }
+
+        impl<'a> From<Limits<'a>> for LimitsReport {
+            fn from(limits: Limits<'a>) -> Self {
+                Self {
+                    $($name: limits.$name(),)*
+                }
+            }
+        }
+    )
+}
+
+limits_report! {
+    max_image_dimension_1d: u32,
+    max_image_dimension_2d: u32,
+    max_image_dimension_3d: u32,
+    max_image_dimension_cube: u32,
+    max_image_array_layers: u32,
+    max_texel_buffer_elements: u32,
+    max_uniform_buffer_range: u32,
+    max_storage_buffer_range: u32,
+    max_push_constants_size: u32,
+    max_memory_allocation_count: u32,
+    max_sampler_allocation_count: u32,
+    buffer_image_granularity: u64,
+    sparse_address_space_size: u64,
+    max_bound_descriptor_sets: u32,
+    max_per_stage_descriptor_samplers: u32,
+    max_per_stage_descriptor_uniform_buffers: u32,
+    max_per_stage_descriptor_storage_buffers: u32,
+    max_per_stage_descriptor_sampled_images: u32,
+    max_per_stage_descriptor_storage_images: u32,
+    max_per_stage_descriptor_input_attachments: u32,
+    max_per_stage_resources: u32,
+    max_descriptor_set_samplers: u32,
+    max_descriptor_set_uniform_buffers: u32,
+    max_descriptor_set_uniform_buffers_dynamic: u32,
+    max_descriptor_set_storage_buffers: u32,
+    max_descriptor_set_storage_buffers_dynamic: u32,
+    max_descriptor_set_sampled_images: u32,
+    max_descriptor_set_storage_images: u32,
+    max_descriptor_set_input_attachments: u32,
+    max_vertex_input_attributes: u32,
+    max_vertex_input_bindings: u32,
+    max_vertex_input_attribute_offset: u32,
+    max_vertex_input_binding_stride: u32,
+    max_vertex_output_components: u32,
+    max_tessellation_generation_level: u32,
+    max_tessellation_patch_size: u32,
+    max_tessellation_control_per_vertex_input_components: u32,
+    max_tessellation_control_per_vertex_output_components: u32,
+    max_tessellation_control_per_patch_output_components: u32,
+    max_tessellation_control_total_output_components: u32,
+    max_tessellation_evaluation_input_components: u32,
+    max_tessellation_evaluation_output_components: u32,
+    max_geometry_shader_invocations: u32,
+    max_geometry_input_components: u32,
+    max_geometry_output_components: u32,
+    max_geometry_output_vertices: u32,
+    max_geometry_total_output_components: u32,
+    max_fragment_input_components: u32,
+    max_fragment_output_attachments: u32,
+    max_fragment_dual_src_attachments: u32,
+    max_fragment_combined_output_resources: u32,
+    max_compute_shared_memory_size: u32,
+    max_compute_work_group_count: [u32; 3],
+    max_compute_work_group_invocations: u32,
+    max_compute_work_group_size: [u32; 3],
+    sub_pixel_precision_bits: u32,
+    sub_texel_precision_bits: u32,
+    mipmap_precision_bits: u32,
+    max_draw_indexed_index_value: u32,
+    max_draw_indirect_count: u32,
+    max_sampler_lod_bias: f32,
+    max_sampler_anisotropy: f32,
+    max_viewports: u32,
+    max_viewport_dimensions: [u32; 2],
+    viewport_bounds_range: [f32; 2],
+    viewport_sub_pixel_bits: u32,
+    min_memory_map_alignment: usize,
+    min_texel_buffer_offset_alignment: u64,
+    min_uniform_buffer_offset_alignment: u64,
+    min_storage_buffer_offset_alignment: u64,
+    min_texel_offset: i32,
+    max_texel_offset: u32,
+    min_texel_gather_offset: i32,
+    max_texel_gather_offset: u32,
+    min_interpolation_offset: f32,
+    max_interpolation_offset: f32,
+    sub_pixel_interpolation_offset_bits: u32,
+    max_framebuffer_width: u32,
+    max_framebuffer_height: u32,
+    max_framebuffer_layers: u32,
+    framebuffer_color_sample_counts: u32,
+    framebuffer_depth_sample_counts: u32,
+    framebuffer_stencil_sample_counts: u32,
+    framebuffer_no_attachments_sample_counts: u32,
+    max_color_attachments: u32,
+    sampled_image_color_sample_counts: u32,
+    sampled_image_integer_sample_counts: u32,
+    sampled_image_depth_sample_counts: u32,
+    sampled_image_stencil_sample_counts: u32,
+    storage_image_sample_counts: u32,
+    max_sample_mask_words: u32,
+    timestamp_compute_and_graphics: u32,
+    timestamp_period: f32,
+    max_clip_distances: u32,
+    max_cull_distances: u32,
+    max_combined_clip_and_cull_distances: u32,
+    discrete_queue_priorities: u32,
+    point_size_range: [f32; 2],
+    line_width_range: [f32; 2],
+    point_size_granularity: f32,
+    line_width_granularity: f32,
+    strict_lines: u32,
+    standard_sample_locations: u32,
+    optimal_buffer_copy_offset_alignment: u64,
+    optimal_buffer_copy_row_pitch_alignment: u64,
+    non_coherent_atom_size: u64,
+}
+
+impl DeviceReport {
+    pub fn new(device: &PhysicalDevice) -> Self {
+        let memory_heaps = device
+            .memory_heaps()
+            .map(|heap| MemoryHeapReport {
+                id: heap.id(),
+                size: heap.size(),
+                device_local: heap.is_device_local(),
+            })
+            .collect();
+
+        let memory_types = device
+            .memory_types()
+            .map(|ty| MemoryTypeReport {
+                id: ty.id(),
+                heap: ty.heap().id(),
+                device_local: ty.is_device_local(),
+                host_visible: ty.is_host_visible(),
+                host_coherent: ty.is_host_coherent(),
+                host_cached: ty.is_host_cached(),
+                lazily_allocated: ty.is_lazily_allocated(),
+            })
+            .collect();
+
+        let queue_families = device
+            .queue_families()
+            .map(|family| QueueFamilyReport {
+                id: family.id(),
+                queues_count: family.queues_count(),
+                graphics: family.supports_graphics(),
+                compute: family.supports_compute(),
+                transfer: family.explicitly_supports_transfers(),
+                sparse_binding: family.supports_sparse_binding(),
+                timestamp_valid_bits: family.timestamp_valid_bits(),
+                min_image_transfer_granularity: family.min_image_transfer_granularity(),
+            })
+            .collect();
+
+        let mut extensions: Vec<String> = RawDeviceExtensions::supported_by_device(*device)
+            .iter()
+            .map(|name| name.to_string_lossy().into_owned())
+            .collect();
+        extensions.sort();
+
+        Self {
+            name: device.name(),
+            device_type: format!("{:?}", device.ty()),
+            api_version: device.api_version().to_string(),
+            driver_version: device.driver_version(),
+            pci_vendor_id: device.pci_vendor_id(),
+            pci_device_id: device.pci_device_id(),
+            uuid: uuid_to_string(device.uuid()),
+            limits: device.limits().into(),
+            memory_heaps,
+            memory_types,
+            queue_families,
+            features: supported_features(device),
+            extensions,
+            surface: None,
+        }
+    }
+
+    /// Adds the formats and present modes the device supports for the surface.
+    pub fn with_surface<W>(mut self, device: &PhysicalDevice, surface: &Surface<W>) -> Self {
+        let caps = match surface.capabilities(*device) {
+            Ok(caps) => caps,
+            Err(_) => return self,
+        };
+
+        self.surface = Some(SurfaceReport {
+            min_image_count: caps.min_image_count,
+            max_image_count: caps.max_image_count,
+            current_extent: caps.current_extent,
+            formats: caps
+                .supported_formats
+                .iter()
+                .map(|(format, color_space)| SurfaceFormatReport {
+                    format: format!("{:?}", format),
+                    color_space: format!("{:?}", color_space),
+                })
+                .collect(),
+            present_modes: caps
+                .present_modes
+                .iter()
+                .map(|mode| format!("{:?}", mode))
+                .collect(),
+        });
+
+        self
+    }
+}
+
+/// Serializes the reports of every device as a single JSON array.
+pub fn reports_to_json(reports: &[DeviceReport]) -> String {
+    serde_json::to_string_pretty(reports).expect("A device report is always valid JSON")
+}
+
+/// Serializes the reports of every device as a TOML array of `[[device]]` tables. Fails
+/// when an integer doesn't fit in the signed 64 bits TOML allows.
+pub fn reports_to_toml(reports: &[DeviceReport]) -> Result<String, toml::ser::Error> {
+    #[derive(Serialize)]
+    struct Reports<'a> {
+        device: &'a [DeviceReport],
+    }
+
+    // Going through a `Value` orders plain values before tables, as TOML requires
+    toml::Value::try_from(Reports { device: reports }).map(|value| value.to_string())
+}
+
+/// Names of the features the device supports. vulkano only exposes them as struct
+/// fields, so they are read back from the `Debug` output, `Features { name: bool, .. }`.
+fn supported_features(device: &PhysicalDevice) -> Vec<String> {
+    let debug = format!("{:?}", device.supported_features());
+    let fields = debug
+        .trim_start_matches("Features")
+        .trim_matches(|c: char| c == '{' || c == '}' || c.is_whitespace());
+
+    fields
+        .split(',')
+        .filter_map(|field| {
+            let mut parts = field.split(':').map(str::trim);
+            match (parts.next(), parts.next()) {
+                (Some(name), Some("true")) => Some(name.to_string()),
+                _ => None,
+            }
+        })
+        .collect()
 }
@@ -0,0 +1,186 @@
+use std::fmt;
+use std::sync::Arc;
+
+use vulkano::device::{DeviceExtensions, Features};
+use vulkano::instance::{Instance, PhysicalDevice, PhysicalDeviceType};
+
+/// Picks the physical device to render with. Devices missing a required extension,
+/// feature or a graphics queue are rejected; the others are ranked by type, then by
+/// the amount of device-local memory. An explicit override, matched against the
+/// device name or UUID, bypasses the ranking.
+pub struct DeviceSelector {
+    required_extensions: DeviceExtensions,
+    required_features: Features,
+    device_override: Option<String>,
+}
+
+/// The device that won, and why.
+pub struct SelectedDevice<'a> {
+    pub physical_device: PhysicalDevice<'a>,
+    pub reason: String,
+}
+
+#[derive(Debug)]
+pub enum DeviceSelectionError {
+    /// No device supports the required extensions, features and queues.
+    NoSuitableDevice,
+    /// The override matches no device, or only unsuitable ones.
+    NoMatchingDevice(String),
+}
+
+impl fmt::Display for DeviceSelectionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeviceSelectionError::NoSuitableDevice => write!(f, "no suitable physical device"),
+            DeviceSelectionError::NoMatchingDevice(device_override) => write!(
+                f,
+                "no suitable physical device matches `{}`",
+                device_override
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DeviceSelectionError {}
+
+impl DeviceSelector {
+    pub fn new() -> Self {
+        Self {
+            required_extensions: DeviceExtensions::none(),
+            required_features: Features::none(),
+            device_override: None,
+        }
+    }
+
+    pub fn with_extensions(mut self, extensions: DeviceExtensions) -> Self {
+        self.required_extensions = extensions;
+        self
+    }
+
+    pub fn with_features(mut self, features: Features) -> Self {
+        self.required_features = features;
+        self
+    }
+
+    /// Selects the device whose name contains `device_override` (case-insensitive),
+    /// or whose UUID is `device_override`, with or without dashes.
+    pub fn with_override(mut self, device_override: Option<String>) -> Self {
+        self.device_override = device_override;
+        self
+    }
+
+    pub fn select<'a>(
+        &self,
+        instance: &'a Arc<Instance>,
+    ) -> Result<SelectedDevice<'a>, DeviceSelectionError> {
+        let mut candidates: Vec<_> = PhysicalDevice::enumerate(instance)
+            .filter(|device| self.rejection(device).is_none())
+            .collect();
+
+        if let Some(device_override) = &self.device_override {
+            candidates.retain(|device| matches_override(device, device_override));
+
+            return match candidates.first() {
+                Some(&physical_device) => Ok(SelectedDevice {
+                    physical_device,
+                    reason: format!("matches the override `{}`", device_override),
+                }),
+                None => Err(DeviceSelectionError::NoMatchingDevice(
+                    device_override.clone(),
+                )),
+            };
+        }
+
+        // `max_by_key` keeps the last maximum, reversing keeps enumeration order on ties
+        let physical_device = candidates
+            .into_iter()
+            .rev()
+            .max_by_key(|device| (type_rank(device.ty()), device_local_memory(device)))
+            .ok_or(DeviceSelectionError::NoSuitableDevice)?;
+
+        let reason = format!(
+            "best ranked {:?} with {} MiB of device-local memory",
+            physical_device.ty(),
+            device_local_memory(&physical_device) / (1024 * 1024)
+        );
+
+        Ok(SelectedDevice {
+            physical_device,
+            reason,
+        })
+    }
+
+    /// Why the device can't be used at all, if it can't.
+    pub fn rejection(&self, device: &PhysicalDevice) -> Option<String> {
+        let supported_extensions = DeviceExtensions::supported_by_device(*device);
+        let missing_extensions = self.required_extensions.difference(&supported_extensions);
+        if missing_extensions != DeviceExtensions::none() {
+            return Some(format!("missing extensions {:?}", missing_extensions));
+        }
+
+        if !device.supported_features().superset_of(&self.required_features) {
+            return Some(String::from("missing required features"));
+        }
+
+        if !device.queue_families().any(|q| q.supports_graphics()) {
+            return Some(String::from("no graphics queue"));
+        }
+
+        None
+    }
+}
+
+impl Default for DeviceSelector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn type_rank(ty: PhysicalDeviceType) -> u32 {
+    match ty {
+        PhysicalDeviceType::DiscreteGpu => 4,
+        PhysicalDeviceType::IntegratedGpu => 3,
+        PhysicalDeviceType::VirtualGpu => 2,
+        PhysicalDeviceType::Cpu => 1,
+        PhysicalDeviceType::Other => 0,
+    }
+}
+
+fn device_local_memory(device: &PhysicalDevice) -> usize {
+    device
+        .memory_heaps()
+        .filter(|heap| heap.is_device_local())
+        .map(|heap| heap.size())
+        .sum()
+}
+
+fn matches_override(device: &PhysicalDevice, device_override: &str) -> bool {
+    if let Some(uuid) = parse_uuid(device_override) {
+        return device.uuid() == &uuid;
+    }
+
+    device
+        .name()
+        .to_lowercase()
+        .contains(&device_override.to_lowercase())
+}
+
+/// Parses a UUID written as 32 hexadecimal digits, dashes being ignored.
+fn parse_uuid(s: &str) -> Option<[u8; 16]> {
+    let digits: Vec<u8> = s
+        .chars()
+        .filter(|&c| c != '-')
+        .map(|c| c.to_digit(16).map(|d| d as u8))
+        .collect::<Option<_>>()?;
+
+    if digits.len() != 32 {
+        return None;
+    }
+
+    let mut uuid = [0u8; 16];
+    for (byte, pair) in uuid.iter_mut().zip(digits.chunks(2)) {
+        *byte = pair[0] << 4 | pair[1];
+    }
+
+    Some(uuid)
+}
@@ -0,0 +1,236 @@
+use std::fmt;
+
+use vulkano::buffer::cpu_access::{ReadLockError, WriteLockError};
+use vulkano::command_buffer::submit::SubmitCommandBufferError;
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilderContextError, BeginRenderPassError, BuildError, CommandBufferExecError,
+    CopyBufferError, CopyBufferImageError, DrawError, DrawIndexedError,
+};
+use vulkano::descriptor::descriptor_set::{
+    PersistentDescriptorSetBuildError, PersistentDescriptorSetError,
+};
+use vulkano::device::DeviceCreationError;
+use vulkano::framebuffer::{FramebufferCreationError, RenderPassCreationError};
+use vulkano::image::ImageCreationError;
+use vulkano::instance::InstanceCreationError;
+use vulkano::memory::DeviceMemoryAllocError;
+use vulkano::pipeline::GraphicsPipelineCreationError;
+use vulkano::swapchain::{AcquireError, CapabilitiesError, ColorSpace, SwapchainCreationError};
+use vulkano::sync::{FenceWaitError, FlushError};
+use vulkano::OomError;
+
+use super::device_selector::DeviceSelectionError;
+use super::resources::loader::obj::LoadError;
+
+/// Everything that can go wrong in the renderer. Most variants wrap the vulkano
+/// error of the same name.
+#[derive(Debug)]
+pub enum Error {
+    IoError(std::io::Error),
+    LoadError(LoadError),
+    DeviceSelectionError(DeviceSelectionError),
+    InstanceCreationError(InstanceCreationError),
+    DeviceCreationError(DeviceCreationError),
+    OomError(OomError),
+    DeviceMemoryAllocError(DeviceMemoryAllocError),
+    ImageCreationError(ImageCreationError),
+    FramebufferCreationError(FramebufferCreationError),
+    PersistentDescriptorSetError(PersistentDescriptorSetError),
+    PersistentDescriptorSetBuildError(PersistentDescriptorSetBuildError),
+    BeginRenderPassError(BeginRenderPassError),
+    AutoCommandBufferBuilderContextError(AutoCommandBufferBuilderContextError),
+    DrawError(DrawError),
+    DrawIndexedError(DrawIndexedError),
+    CopyBufferError(CopyBufferError),
+    CopyBufferImageError(CopyBufferImageError),
+    BuildError(BuildError),
+    CommandBufferExecError(CommandBufferExecError),
+    SubmitCommandBufferError(SubmitCommandBufferError),
+    FlushError(FlushError),
+    FenceWaitError(FenceWaitError),
+    ReadLockError(ReadLockError),
+    WriteLockError(WriteLockError),
+    GraphicsPipelineCreationError(GraphicsPipelineCreationError),
+    RenderPassCreationError(RenderPassCreationError),
+    CapabilitiesError(CapabilitiesError),
+    SwapchainCreationError(SwapchainCreationError),
+    AcquireError(AcquireError),
+    WindowCreationError(vulkano_win::CreationError),
+    /// A mesh was rendered before `Mesh::bake` uploaded its buffers.
+    NotBaked,
+    /// The format can't be read back, see `offscreen::READABLE_FORMATS`.
+    UnreadableFormat(vulkano::format::Format),
+    /// The surface supports no format in the configured color space.
+    NoSurfaceFormat(ColorSpace),
+    /// The device supports none of the depth formats the renderer can use.
+    NoDepthFormat,
+    /// The surface supports no composite alpha mode.
+    NoCompositeAlpha,
+    /// The device was created without one of the queues it was asked for.
+    MissingQueue,
+    /// The render pass has no subpass of this index.
+    MissingSubpass(u32),
+    /// The pixels read back from an image don't match its dimensions.
+    ReadbackSizeMismatch,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+macro_rules! impl_from {
+    ($($variant:ident($ty:ty),)*) => (
+        $(
+            impl From<$ty> for Error {
+                fn from(error: $ty) -> Self {
+                    Error::$variant(error)
+                }
+            }
+        )*
+    )
+}
+
+impl_from! {
+    IoError(std::io::Error),
+    LoadError(LoadError),
+    DeviceSelectionError(DeviceSelectionError),
+    InstanceCreationError(InstanceCreationError),
+    DeviceCreationError(DeviceCreationError),
+    OomError(OomError),
+    DeviceMemoryAllocError(DeviceMemoryAllocError),
+    ImageCreationError(ImageCreationError),
+    FramebufferCreationError(FramebufferCreationError),
+    PersistentDescriptorSetError(PersistentDescriptorSetError),
+    PersistentDescriptorSetBuildError(PersistentDescriptorSetBuildError),
+    BeginRenderPassError(BeginRenderPassError),
+    AutoCommandBufferBuilderContextError(AutoCommandBufferBuilderContextError),
+    DrawError(DrawError),
+    DrawIndexedError(DrawIndexedError),
+    CopyBufferError(CopyBufferError),
+    CopyBufferImageError(CopyBufferImageError),
+    BuildError(BuildError),
+    CommandBufferExecError(CommandBufferExecError),
+    SubmitCommandBufferError(SubmitCommandBufferError),
+    FlushError(FlushError),
+    FenceWaitError(FenceWaitError),
+    ReadLockError(ReadLockError),
+    WriteLockError(WriteLockError),
+    GraphicsPipelineCreationError(GraphicsPipelineCreationError),
+    RenderPassCreationError(RenderPassCreationError),
+    CapabilitiesError(CapabilitiesError),
+    SwapchainCreationError(SwapchainCreationError),
+    AcquireError(AcquireError),
+    WindowCreationError(vulkano_win::CreationError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::IoError(e) => write!(f, "{}", e),
+            Error::LoadError(e) => write!(f, "failed to load a model: {}", e),
+            Error::DeviceSelectionError(e) => write!(f, "{}", e),
+            Error::InstanceCreationError(e) => write!(f, "failed to create the instance: {}", e),
+            Error::DeviceCreationError(e) => write!(f, "failed to create the device: {}", e),
+            Error::OomError(e) => write!(f, "{}", e),
+            Error::DeviceMemoryAllocError(e) => write!(f, "{}", e),
+            Error::ImageCreationError(e) => write!(f, "failed to create an image: {}", e),
+            Error::FramebufferCreationError(e) => {
+                write!(f, "failed to create a framebuffer: {}", e)
+            }
+            Error::PersistentDescriptorSetError(e) => write!(f, "invalid descriptor set: {}", e),
+            Error::PersistentDescriptorSetBuildError(e) => {
+                write!(f, "failed to build a descriptor set: {}", e)
+            }
+            Error::BeginRenderPassError(e) => write!(f, "failed to begin a render pass: {}", e),
+            Error::AutoCommandBufferBuilderContextError(e) => {
+                write!(f, "failed to record a command: {}", e)
+            }
+            Error::DrawError(e) => write!(f, "failed to draw: {}", e),
+            Error::DrawIndexedError(e) => write!(f, "failed to draw: {}", e),
+            Error::CopyBufferError(e) => write!(f, "failed to copy a buffer: {}", e),
+            Error::CopyBufferImageError(e) => write!(f, "failed to copy an image: {}", e),
+            Error::BuildError(e) => write!(f, "failed to build a command buffer: {}", e),
+            Error::CommandBufferExecError(e) => {
+                write!(f, "failed to execute a command buffer: {}", e)
+            }
+            Error::SubmitCommandBufferError(e) => {
+                write!(f, "failed to submit a command buffer: {}", e)
+            }
+            Error::FlushError(e) => write!(f, "{}", e),
+            Error::FenceWaitError(e) => write!(f, "{}", e),
+            Error::ReadLockError(e) => write!(f, "failed to read a buffer: {}", e),
+            Error::WriteLockError(e) => write!(f, "failed to write a buffer: {}", e),
+            Error::GraphicsPipelineCreationError(e) => {
+                write!(f, "failed to create a pipeline: {}", e)
+            }
+            Error::RenderPassCreationError(e) => {
+                write!(f, "failed to create a render pass: {}", e)
+            }
+            Error::CapabilitiesError(e) => {
+                write!(f, "failed to get the surface capabilities: {}", e)
+            }
+            Error::SwapchainCreationError(e) => write!(f, "failed to create the swapchain: {}", e),
+            Error::AcquireError(e) => write!(f, "failed to acquire a swapchain image: {}", e),
+            Error::WindowCreationError(e) => write!(f, "failed to create the window: {}", e),
+            Error::NotBaked => write!(f, "the mesh must be baked before being rendered"),
+            Error::UnreadableFormat(format) => write!(f, "format {:?} can't be read back", format),
+            Error::NoSurfaceFormat(color_space) => {
+                write!(
+                    f,
+                    "the surface supports no format in the {:?} color space",
+                    color_space
+                )
+            }
+            Error::NoDepthFormat => write!(f, "the device supports no usable depth format"),
+            Error::NoCompositeAlpha => write!(f, "the surface supports no composite alpha mode"),
+            Error::MissingQueue => write!(f, "the device is missing a requested queue"),
+            Error::MissingSubpass(index) => write!(f, "the render pass has no subpass {}", index),
+            Error::ReadbackSizeMismatch => {
+                write!(f, "the pixels read back don't match the image dimensions")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::IoError(e) => Some(e),
+            Error::LoadError(e) => Some(e),
+            Error::DeviceSelectionError(e) => Some(e),
+            Error::InstanceCreationError(e) => Some(e),
+            Error::DeviceCreationError(e) => Some(e),
+            Error::OomError(e) => Some(e),
+            Error::DeviceMemoryAllocError(e) => Some(e),
+            Error::ImageCreationError(e) => Some(e),
+            Error::FramebufferCreationError(e) => Some(e),
+            Error::PersistentDescriptorSetError(e) => Some(e),
+            Error::PersistentDescriptorSetBuildError(e) => Some(e),
+            Error::BeginRenderPassError(e) => Some(e),
+            Error::AutoCommandBufferBuilderContextError(e) => Some(e),
+            Error::DrawError(e) => Some(e),
+            Error::DrawIndexedError(e) => Some(e),
+            Error::CopyBufferError(e) => Some(e),
+            Error::CopyBufferImageError(e) => Some(e),
+            Error::BuildError(e) => Some(e),
+            Error::CommandBufferExecError(e) => Some(e),
+            Error::SubmitCommandBufferError(e) => Some(e),
+            Error::FlushError(e) => Some(e),
+            Error::FenceWaitError(e) => Some(e),
+            Error::ReadLockError(e) => Some(e),
+            Error::WriteLockError(e) => Some(e),
+            Error::GraphicsPipelineCreationError(e) => Some(e),
+            Error::RenderPassCreationError(e) => Some(e),
+            Error::CapabilitiesError(e) => Some(e),
+            Error::SwapchainCreationError(e) => Some(e),
+            Error::AcquireError(e) => Some(e),
+            Error::WindowCreationError(e) => Some(e),
+            Error::NotBaked
+            | Error::UnreadableFormat(_)
+            | Error::NoSurfaceFormat(_)
+            | Error::NoDepthFormat
+            | Error::NoCompositeAlpha
+            | Error::MissingQueue
+            | Error::MissingSubpass(_)
+            | Error::ReadbackSizeMismatch => None,
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::sync::Arc;
+
+use vulkano::device::Device;
+use vulkano::format::Format;
+use vulkano::image::attachment::AttachmentImage;
+use vulkano::instance::PhysicalDevice;
+use vulkano::swapchain::ColorSpace;
+
+/// Color formats tried, in order, when the configured one is not supported by the
+/// surface. 8-bit formats come first since that is what the shaders are written for.
+pub const COLOR_FORMAT_FALLBACKS: &[Format] = &[
+    Format::B8G8R8A8Unorm,
+    Format::B8G8R8A8Srgb,
+    Format::R8G8B8A8Unorm,
+    Format::R8G8B8A8Srgb,
+    Format::A8B8G8R8UnormPack32,
+    Format::A8B8G8R8SrgbPack32,
+    Format::A2B10G10R10UnormPack32,
+    Format::A2R10G10B10UnormPack32,
+    Format::R16G16B16A16Sfloat,
+];
+
+/// Depth formats tried, in order, when the configured one cannot be used as a depth
+/// attachment. `D16Unorm` is last because the Vulkan spec guarantees its support.
+pub const DEPTH_FORMAT_FALLBACKS: &[Format] = &[
+    Format::D32Sfloat,
+    Format::D24Unorm_S8Uint,
+    Format::X8_D24UnormPack32,
+    Format::D32Sfloat_S8Uint,
+    Format::D16Unorm,
+];
+
+/// Picks the surface format closest to the wanted one in `color_space`, trying the
+/// fallback formats then any other format of that color space. Returns `None` if the
+/// surface supports no format in `color_space`.
+pub fn select_surface_format(
+    supported: &[(Format, ColorSpace)],
+    format: Format,
+    color_space: ColorSpace,
+) -> Option<Format> {
+    let in_color_space: Vec<Format> = supported
+        .iter()
+        .filter(|&&(_, c)| c == color_space)
+        .map(|&(f, _)| f)
+        .collect();
+
+    std::iter::once(format)
+        .chain(COLOR_FORMAT_FALLBACKS.iter().cloned())
+        .find(|f| in_color_space.contains(f))
+        .or_else(|| in_color_space.first().cloned())
+}
+
+/// Picks the wanted depth format if the device can use it as a depth attachment,
+/// or the first supported one from `DEPTH_FORMAT_FALLBACKS`.
+pub fn select_depth_format(device: &Arc<Device>, format: Format) -> Option<Format> {
+    std::iter::once(format)
+        .chain(DEPTH_FORMAT_FALLBACKS.iter().cloned())
+        .find(|&f| supports_depth_attachment(device, f))
+}
+
+/// Picks the highest sample count up to `samples` that the device supports for both
+/// color and depth attachments. A single sample is always supported.
+pub fn select_sample_count(physical_device: PhysicalDevice, samples: u32) -> u32 {
+    let limits = physical_device.limits();
+    let supported =
+        limits.framebuffer_color_sample_counts() & limits.framebuffer_depth_sample_counts();
+
+    (0..=samples.max(1).trailing_zeros())
+        .rev()
+        .map(|exponent| 1 << exponent)
+        .find(|&count| supported & count != 0)
+        .unwrap_or(1)
+}
+
+/// vulkano doesn't expose the format properties, but refuses to create an image
+/// whose format lacks the features its usage needs, so a tiny attachment tells us.
+fn supports_depth_attachment(device: &Arc<Device>, format: Format) -> bool {
+    AttachmentImage::transient(device.clone(), [1, 1], format).is_ok()
+}
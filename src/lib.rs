@@ -1,26 +1,47 @@
+pub mod app;
 pub mod camera;
 pub mod color;
+pub mod config;
 pub mod device_infos;
+pub mod device_selector;
+pub mod error;
+pub mod format;
 pub mod input;
 pub mod metrics;
+pub mod offscreen;
+pub mod presenter;
+pub mod recording;
 pub mod resources;
+pub mod time;
 pub mod transform;
+pub mod upload;
+pub mod validation;
 pub mod vertex;
 pub mod descriptors;
 
-use std::fs::File;
-use std::io::Read;
-use std::path::Path;
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use cgmath::{Matrix4, Rad, SquareMatrix};
-use serde::Deserialize;
-use vulkano::device::{Device, Queue};
-use vulkano::format::Format;
-use vulkano::instance::{Instance, PhysicalDevice};
-
-use device_infos::print_infos;
+use log::{info, warn};
+use vulkano::command_buffer::DynamicState;
+use vulkano::device::{Device, DeviceExtensions, Queue};
+use vulkano::format::{ClearValue, Format};
+use vulkano::instance::{Instance, InstanceExtensions, PhysicalDevice, QueueFamily};
+use vulkano::pipeline::viewport::Viewport;
+
+use device_infos::log_infos;
+use device_selector::{DeviceSelectionError, DeviceSelector};
+use format::{select_depth_format, select_sample_count};
 use input::Input;
+use validation::{validation_layer_available, Validation, VALIDATION_LAYER};
+
+pub use config::{
+    ColorSpaceDef, ConfigChanges, ConfigError, ConfigSource, ConfigWatcher, FormatDef,
+    LayeredConfig, PresentModeDef, RendererConfig, RendererConfigBuilder,
+};
+pub use app::{record, record_headless, run, run_with_watcher, App, FrameContext};
+pub use error::{Error, Result};
 
 pub struct ApplicationState {
     pub is_running: bool,
@@ -51,50 +72,20 @@ impl ApplicationState {
             100.0,
         );
     }
-}
-
-#[derive(Deserialize)]
-#[serde(remote = "Format")]
-pub enum FormatDef {
-    B8G8R8A8Srgb,
-    B8G8R8A8Unorm,
-}
 
-impl From<FormatDef> for Format {
-    fn from(def: FormatDef) -> Format {
-        match def {
-            FormatDef::B8G8R8A8Srgb => Format::B8G8R8A8Srgb,
-            FormatDef::B8G8R8A8Unorm => Format::B8G8R8A8Unorm,
+    /// The dynamic state of the pipelines, with a viewport covering the dimensions.
+    pub fn dynamic_state(&self) -> DynamicState {
+        DynamicState {
+            viewports: Some(vec![Viewport {
+                origin: [0.0, 0.0],
+                dimensions: self.dimensions,
+                depth_range: 0.0..1.0,
+            }]),
+            ..DynamicState::none()
         }
     }
 }
 
-#[derive(Debug, Deserialize)]
-pub struct RendererConfig {
-    pub fps: Option<f32>,
-    pub width: f32,
-    pub height: f32,
-    pub line_width: f32,
-    pub clear_color: [f32; 3],
-    #[serde(with = "FormatDef")]
-    pub format: vulkano::format::Format,
-}
-
-impl RendererConfig {
-    pub fn load_from_file<P: AsRef<Path>>(p: P) -> Self {
-        // TODO: Fix this
-        let mut config_file = match File::open(p) {
-            Ok(f) => f,
-            Err(e) => panic!(format!("{:?}", e.kind())),
-        };
-
-        let mut config_string = String::new();
-        config_file.read_to_string(&mut config_string).unwrap();
-        let config: Self = toml::from_str(config_string.as_str()).unwrap();
-        config
-    }
-}
-
 pub struct Renderer {
     pub config: RendererConfig,
     pub device: Arc<Device>,
@@ -102,68 +93,253 @@ pub struct Renderer {
     pub transfer_queue: Arc<Queue>,
     pub state: ApplicationState,
     pub input: Input,
+    validation: Option<Validation>,
+    pending_screenshot: Option<PathBuf>,
 }
 
 impl Renderer {
-    pub fn create(config: RendererConfig) -> Self {
-        let instance = {
-            let extensions = vulkano_win::required_extensions();
-            Instance::new(None, &extensions, None).expect("Failed to create instance")
+    /// Creates an instance with the extensions needed to present to a window.
+    pub fn create_instance() -> Result<Arc<Instance>> {
+        Self::create_instance_with(&vulkano_win::required_extensions(), false)
+    }
+
+    /// Creates an instance with `extensions`, plus the validation layer and the debug
+    /// utils extension when `validation` is set and they are available.
+    fn create_instance_with(
+        extensions: &InstanceExtensions,
+        validation: bool,
+    ) -> Result<Arc<Instance>> {
+        let debug_utils_supported = InstanceExtensions::supported_by_core()
+            .map(|supported| supported.ext_debug_utils)
+            .unwrap_or(false);
+
+        let layers: &[&str] = if !validation {
+            &[]
+        } else if validation_layer_available() && debug_utils_supported {
+            &[VALIDATION_LAYER]
+        } else {
+            warn!("Validation was requested but {} is not installed", VALIDATION_LAYER);
+            &[]
+        };
+
+        let extensions = InstanceExtensions {
+            ext_debug_utils: !layers.is_empty(),
+            ..*extensions
+        };
+
+        Ok(Instance::new(None, &extensions, layers.iter().cloned())?)
+    }
+
+    pub fn create(config: RendererConfig) -> Result<Self> {
+        let device_extensions = DeviceExtensions {
+            khr_swapchain: true,
+            ..DeviceExtensions::none()
         };
 
-        let physical_device = PhysicalDevice::enumerate(&instance)
-            .next()
-            .expect("No physical device found");
-        println!("Found device:");
-        print_infos(&physical_device);
+        let instance =
+            Self::create_instance_with(&vulkano_win::required_extensions(), config.validation)?;
+
+        Self::create_with_instance(instance, &device_extensions, config)
+    }
+
+    /// Creates a renderer without any surface or swapchain extension, to render into
+    /// an `OffscreenTarget` on machines without a display.
+    pub fn create_headless(config: RendererConfig) -> Result<Self> {
+        let instance = Self::create_instance_with(&InstanceExtensions::none(), config.validation)?;
+
+        Self::create_with_instance(instance, &DeviceExtensions::none(), config)
+    }
+
+    fn create_with_instance(
+        instance: Arc<Instance>,
+        device_extensions: &DeviceExtensions,
+        mut config: RendererConfig,
+    ) -> Result<Self> {
+        let validation = if config.validation {
+            Validation::new(&instance, config.panic_on_validation_error)
+        } else {
+            None
+        };
+
+        let selected = DeviceSelector::new()
+            .with_extensions(*device_extensions)
+            .with_override(config.device.clone())
+            .select(&instance)?;
+        let physical_device = selected.physical_device;
+        info!(
+            "Selected device {} ({})",
+            physical_device.name(),
+            selected.reason
+        );
+        log_infos(&physical_device);
+
+        let msaa_samples = select_sample_count(physical_device, config.msaa_samples);
+        if msaa_samples != config.msaa_samples {
+            warn!(
+                "{} MSAA samples are not supported, falling back to {}",
+                config.msaa_samples, msaa_samples
+            );
+            config.msaa_samples = msaa_samples;
+        }
 
         let (device, graphics_queue, transfer_queue) =
-            Self::create_device_and_queue(physical_device);
+            Self::create_device_and_queue(physical_device, device_extensions)?;
 
-        Self {
+        Ok(Self {
             config,
             device,
             graphics_queue,
             transfer_queue,
             state: ApplicationState::new(),
             input: Input::new(),
+            validation,
+            pending_screenshot: None,
+        })
+    }
+
+    /// Saves the next presented frame to `path` as a PNG, see `Presenter::end_frame`.
+    /// The image holds the bytes of the swapchain as displayed, for the Unorm and
+    /// Srgb formats alike.
+    pub fn capture_screenshot<P: Into<PathBuf>>(&mut self, path: P) {
+        self.pending_screenshot = Some(path.into());
+    }
+
+    /// Removes the screenshot request that the presenter could not fulfill.
+    pub(crate) fn take_screenshot_request(&mut self) -> Option<PathBuf> {
+        self.pending_screenshot.take()
+    }
+
+    /// Forgets the errors the validation layer reported since the last call, panicking
+    /// if there were some and `panic_on_validation_error` is set.
+    pub fn check_validation(&self) {
+        if let Some(validation) = &self.validation {
+            validation.check();
+        }
+    }
+
+    /// Applies a reloaded configuration. Changes that need the swapchain or the
+    /// pipelines to be rebuilt go through `ApplicationState::need_recreation`; the
+    /// window size is left to the caller, which owns the window.
+    pub fn apply_config(&mut self, mut config: RendererConfig) -> ConfigChanges {
+        config.msaa_samples =
+            select_sample_count(self.device.physical_device(), config.msaa_samples);
+        let mut changes = ConfigChanges::between(&self.config, &config);
+
+        if changes.format {
+            warn!("Changing the formats or the color space requires a restart");
+            config.format = self.config.format;
+            config.depth_format = self.config.depth_format;
+            config.color_space = self.config.color_space;
+            changes.format = false;
+        }
+
+        if changes.msaa_samples {
+            warn!("Changing the MSAA sample count requires a restart");
+            config.msaa_samples = self.config.msaa_samples;
+            changes.msaa_samples = false;
+        }
+
+        if changes.device {
+            warn!("Changing the device requires a restart");
+            config.device = self.config.device.clone();
+            changes.device = false;
+        }
+
+        if changes.validation {
+            warn!("Changing the validation settings requires a restart");
+            config.validation = self.config.validation;
+            config.panic_on_validation_error = self.config.panic_on_validation_error;
+            changes.validation = false;
         }
+
+        if changes.need_recreation() {
+            self.state.need_recreation = true;
+        }
+
+        self.config = config;
+        changes
+    }
+
+    /// The clear values of the render passes from `resources::pipelines::create_render_pass`,
+    /// which have a resolve attachment when MSAA is enabled.
+    pub fn clear_values(&self) -> Vec<ClearValue> {
+        let mut clear_values = vec![self.config.clear_color.into(), 1f32.into()];
+        if self.config.msaa_samples > 1 {
+            clear_values.push(ClearValue::None);
+        }
+        clear_values
     }
 
+    /// The configured depth format, or the first supported fallback.
+    pub fn select_depth_format(&self) -> Result<Format> {
+        let depth_format = select_depth_format(&self.device, self.config.depth_format)
+            .ok_or(Error::NoDepthFormat)?;
+        if depth_format != self.config.depth_format {
+            warn!(
+                "Depth format {:?} is not supported, falling back to {:?}",
+                self.config.depth_format, depth_format
+            );
+        }
+
+        Ok(depth_format)
+    }
+
+    /// Whether uploads go through a queue of their own family, in which case the
+    /// buffers they fill must be transferred to the graphics queue family.
+    pub fn has_dedicated_transfer_queue(&self) -> bool {
+        self.transfer_queue.family().id() != self.graphics_queue.family().id()
+    }
+
+    /// Creates the device with a graphics queue, and a transfer queue from a family
+    /// dedicated to transfers when the device has one. Otherwise the graphics queue
+    /// is returned for both.
     fn create_device_and_queue(
         physical_device: PhysicalDevice,
-    ) -> (Arc<Device>, Arc<Queue>, Arc<Queue>) {
+        device_extensions: &DeviceExtensions,
+    ) -> Result<(Arc<Device>, Arc<Queue>, Arc<Queue>)> {
+        // The device selector rejects devices without one
         let graphics_queue_family = physical_device
             .queue_families()
             .find(|&q| q.supports_graphics())
-            .expect("Couldn't find a graphical queue family");
+            .ok_or(DeviceSelectionError::NoSuitableDevice)?;
+
+        // Families supporting transfers but neither graphics nor compute map to the
+        // DMA engines of discrete GPUs
+        let transfer_queue_family = physical_device.queue_families().find(|&q| {
+            q.explicitly_supports_transfers() && !q.supports_graphics() && !q.supports_compute()
+        });
+
+        let queue_families = match transfer_queue_family {
+            Some(transfer_queue_family) => {
+                vec![(graphics_queue_family, 0.5), (transfer_queue_family, 0.5)]
+            }
+            None => vec![(graphics_queue_family, 0.5)],
+        };
 
-        let transfer_queue_family = physical_device
-            .queue_families()
-            .find(|&q| q.explicitly_supports_transfers())
-            .unwrap_or(graphics_queue_family);
-
-        let (device, mut queues) = {
-            let device_ext = vulkano::device::DeviceExtensions {
-                khr_swapchain: true,
-                ..vulkano::device::DeviceExtensions::none()
-            };
-
-            Device::new(
-                physical_device,
-                physical_device.supported_features(),
-                &device_ext,
-                [(graphics_queue_family, 0.5), (transfer_queue_family, 0.5)]
-                    .iter()
-                    .cloned(),
-            )
-            .expect("Failed to create device")
+        let (device, queues) = Device::new(
+            physical_device,
+            physical_device.supported_features(),
+            device_extensions,
+            queue_families,
+        )?;
+
+        // The queues are not guaranteed to come in the order they were requested in
+        let queues: Vec<_> = queues.collect();
+        let queue_of = |family: QueueFamily| {
+            queues
+                .iter()
+                .find(|queue| queue.family().id() == family.id())
+                .cloned()
+                .ok_or(Error::MissingQueue)
         };
 
-        let graphics_queue = queues.next().unwrap();
-        let transfer_queue = queues.next().unwrap();
+        let graphics_queue = queue_of(graphics_queue_family)?;
+        let transfer_queue = match transfer_queue_family {
+            Some(transfer_queue_family) => queue_of(transfer_queue_family)?,
+            None => graphics_queue.clone(),
+        };
 
-        (device, graphics_queue, transfer_queue)
+        Ok((device, graphics_queue, transfer_queue))
     }
 }
 
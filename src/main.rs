@@ -1,19 +1,29 @@
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
 
 use renderer::camera::CameraCenter;
 use renderer::color::Color;
+use renderer::device_infos::{reports_to_json, reports_to_toml, DeviceReport};
 use renderer::input::Input;
-use renderer::metrics::{FPSCounter, Timer};
-use renderer::resources::model::Gizmo;
-use renderer::resources::model::Renderable;
+use renderer::metrics::Timer;
+use renderer::offscreen::{select_readable_format, OffscreenTarget};
+use renderer::recording::Recording;
+use renderer::presenter::FRAMES_IN_FLIGHT;
+use renderer::resources::model::{Gizmo, InstancedRenderable};
+use renderer::resources::pipelines::{create_gizmo_pipeline, create_instanced_pipeline, create_render_pass};
+use renderer::resources::{Material, Mesh, MeshInstance};
+use renderer::time::Clock;
+use renderer::transform::Transform;
 use renderer::resources::shaders;
-use renderer::vertex::Vertex;
-use renderer::{ApplicationState, Renderer, RendererConfig};
+use renderer::upload::upload_to_device_local;
+use renderer::vertex::{InstanceData, Vertex};
+use renderer::{
+	App, ConfigWatcher, FrameContext, Renderer, RendererConfig, RendererConfigBuilder,
+};
 
 use vulkano::buffer::{
 	cpu_pool::CpuBufferPool, sys::UnsafeBuffer, BufferAccess, BufferInner, BufferUsage,
-	CpuAccessibleBuffer, DeviceLocalBuffer, TypedBufferAccess, cpu_access::{WriteLock, WriteLockError}
+	CpuAccessibleBuffer, DeviceLocalBuffer, cpu_access::{WriteLock, WriteLockError}
 };
 use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBuffer, DynamicState};
 use vulkano::descriptor::descriptor::{
@@ -23,585 +33,607 @@ use vulkano::descriptor::descriptor_set::{
 	DescriptorsCount, FixedSizeDescriptorSetsPool, PersistentDescriptorSet,
 	UnsafeDescriptorPool, UnsafeDescriptorSetLayout,
 };
-use vulkano::device::{Device, Queue};
-use vulkano::format::Format;
-use vulkano::framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract, Subpass};
-use vulkano::image::attachment::AttachmentImage;
-use vulkano::image::swapchain::SwapchainImage;
-use vulkano::pipeline::{viewport::Viewport, GraphicsPipeline, GraphicsPipelineAbstract};
-use vulkano::swapchain::{PresentMode, Surface, SurfaceTransform, Swapchain};
+use vulkano::framebuffer::RenderPassAbstract;
+use vulkano::instance::PhysicalDevice;
+use vulkano::pipeline::GraphicsPipelineAbstract;
 use vulkano::sync::GpuFuture;
 use vulkano::{VulkanHandle, VulkanObject};
 
 use vulkano_win::VkSurfaceBuild;
-use winit::dpi::{LogicalPosition, LogicalSize};
-use winit::{Event, EventsLoop, Window, WindowBuilder, WindowEvent};
-
-use cgmath::prelude::*;
-use cgmath::{Matrix4, SquareMatrix, Vector3, Rad};
-
-use rand::Rng;
-
-fn create_swapchain(
-	device: Arc<Device>,
-	surface: Arc<Surface<Window>>,
-	queue: Arc<Queue>,
-	state: &mut ApplicationState,
-	config: &RendererConfig,
-	old_swapchain: Option<&Arc<Swapchain<Window>>>,
-) -> (Arc<Swapchain<Window>>, Vec<Arc<SwapchainImage<Window>>>) {
-	let caps = surface
-		.capabilities(device.physical_device())
-		.expect("Failed to get device capabilities");
-
-	let [width, height] = caps.current_extent.unwrap();
-	let alpha = caps.supported_composite_alpha.iter().next().unwrap();
-
-	let format = caps
-		.supported_formats
-		.into_iter()
-		.find(|format| format.0 == config.format)
-		.expect("Failed to find the wanted format")
-		.0;
-
-	state.set_dimensions(width as f32, height as f32);
-
-	let (swapchain, images) = Swapchain::new(
-		device,
-		surface,
-		caps.min_image_count,
-		format,
-		[width, height],
-		1,
-		caps.supported_usage_flags,
-		&queue,
-		SurfaceTransform::Identity,
-		alpha,
-		PresentMode::Fifo,
-		true,
-		old_swapchain,
-	)
-	.expect("Failed to create swapchain");
+use winit::dpi::LogicalPosition;
+use winit::{
+	ElementState, Event, EventsLoop, KeyboardInput, VirtualKeyCode, WindowBuilder, WindowEvent,
+};
 
-	(swapchain, images)
-}
+use cgmath::{Matrix4, Vector3, Rad};
 
-fn create_framebuffers(
-	device: Arc<Device>,
-	images: &Vec<Arc<SwapchainImage<Window>>>,
-	render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
-) -> Vec<Arc<dyn FramebufferAbstract + Send + Sync>> {
-	let dimensions = images[0].dimensions();
-
-	let depth_buffer =
-		AttachmentImage::transient(device.clone(), dimensions, Format::D16Unorm).unwrap();
-
-	images.iter()
-		.map(|image| {
-			Arc::new(
-				Framebuffer::start(render_pass.clone())
-					.add(image.clone())
-					.unwrap()
-					.add(depth_buffer.clone())
-					.unwrap()
-					.build()
-					.unwrap(),
-			) as Arc<dyn FramebufferAbstract + Send + Sync>
-		})
-		.collect()
-}
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
-fn create_pipeline(
-	vs: &shaders::basic::vertex::Shader,
-	fs: &shaders::basic::fragment::Shader,
-	state: &ApplicationState,
+/// The demo scene: a gizmo and randomly placed rotating cubes. It only depends on the
+/// formats of the target, so it can be drawn to a window or offscreen.
+struct Scene {
+	instanced_vertex_shader: shaders::instanced::vertex::Shader,
+	basic_fragment_shader: shaders::basic::fragment::Shader,
+	gizmo_vertex_shader: shaders::gizmo::vertex::Shader,
+	gizmo_fragment_shader: shaders::gizmo::fragment::Shader,
 	render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
-	device: Arc<Device>,
-) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
-	Arc::new(
-		GraphicsPipeline::start()
-			.vertex_input_single_buffer::<Vertex>()
-			.vertex_shader(vs.main_entry_point(), ())
-			.triangle_list()
-			.viewports_dynamic_scissors_irrelevant(1)
-			.viewports(std::iter::once(Viewport {
-				origin: [0.0, 0.0],
-				dimensions: state.dimensions,
-				depth_range: 0.0..1.0,
-			}))
-			.fragment_shader(fs.main_entry_point(), ())
-			.depth_stencil_simple_depth()
-			.render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
-			.cull_mode_back()
-			.build(device.clone())
-			.unwrap(),
-	)
+	pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	gizmo_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	cube: Arc<Mesh>,
+	/// Instances of `cube`, each with its own color.
+	models: Vec<MeshInstance>,
+	/// The model transforms before the last fixed update, to interpolate from.
+	previous_transforms: Vec<Transform>,
+	/// The attributes of every model, for each frame in flight. All the cubes are drawn
+	/// with a single instanced call.
+	instance_buffers: Vec<Arc<CpuAccessibleBuffer<[InstanceData]>>>,
+	model_update_rules: Vec<(Rad<f32>, Rad<f32>, Rad<f32>)>,
+	world_data_uniform_buffer: CpuBufferPool<shaders::basic::vertex::ty::WorldData>,
+	gizmo_uniform_buffer: CpuBufferPool<shaders::gizmo::vertex::ty::Data>,
+	pool: FixedSizeDescriptorSetsPool<Arc<dyn GraphicsPipelineAbstract + Send + Sync>>,
+	gizmo_vertex_buffer: Arc<DeviceLocalBuffer<[Vertex]>>,
+	light_position: Vector3<f32>,
 }
 
-fn main() {
-	let config =
-		RendererConfig::load_from_file("/home/corendos/dev/rust/renderer/renderer.toml");
-	let mut renderer = Renderer::create(config);
-
-	let mut events_loop = EventsLoop::new();
-	let surface = WindowBuilder::new()
-		.with_dimensions(LogicalSize::from((
-			renderer.config.width as f64,
-			renderer.config.height as f64,
-		)))
-		.with_title("Vulkan boilerplate")
-		.build_vk_surface(&events_loop, renderer.device.instance().clone())
-		.unwrap();
-
-	let target_frame_duration = match renderer.config.fps {
-		Some(target) => Some(1.0 / target),
-		None => None,
-	};
-
-	let (mut swapchain, mut images) = create_swapchain(
-		renderer.device.clone(),
-		surface.clone(),
-		renderer.graphics_queue.clone(),
-		&mut renderer.state,
-		&renderer.config,
-		None,
-	);
-
-	let basic_vertex_shader = shaders::basic::vertex::Shader::load(renderer.device.clone())
-		.expect("Failed to create vertex shader");
-	let basic_fragment_shader = shaders::basic::fragment::Shader::load(renderer.device.clone())
-		.expect("Failed to create fragment shader");
-
-	let gizmo_vertex_shader = shaders::gizmo::vertex::Shader::load(renderer.device.clone())
-		.expect("Failed to create vertex shader");
-	let gizmo_fragment_shader = shaders::gizmo::fragment::Shader::load(renderer.device.clone())
-		.expect("Failed to create fragment shader");
-
-	let mut rng = rand::thread_rng();
-	let gizmo = Gizmo::new(2.0);
-
-	let mut models: Vec<_> = (0..2000)
-		.into_iter()
-		.map(|_| {
-			let random_color = Color::<f32>::new(
-				rng.gen_range(0.3, 1.0),
-				rng.gen_range(0.3, 1.0),
-				rng.gen_range(0.3, 1.0),
-			);
-
-			let random_position = Vector3::new(
-				rng.gen_range(-5.0, 5.0),
-				rng.gen_range(-5.0, 5.0),
-				rng.gen_range(-5.0, 5.0),
-			);
-
-			let mut model = renderer::resources::Model::cube(1.0, random_color);
-			model.transform.translate(random_position);
-
-			model.bake(&renderer);
-			model
-		})
-		.collect();
+impl Scene {
+	fn new<R: Rng>(
+		renderer: &Renderer,
+		render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+		rng: &mut R,
+	) -> Self {
+		let instanced_vertex_shader = shaders::instanced::vertex::Shader::load(renderer.device.clone())
+			.expect("Failed to create vertex shader");
+		let basic_fragment_shader = shaders::basic::fragment::Shader::load(renderer.device.clone())
+			.expect("Failed to create fragment shader");
+
+		let gizmo_vertex_shader = shaders::gizmo::vertex::Shader::load(renderer.device.clone())
+			.expect("Failed to create vertex shader");
+		let gizmo_fragment_shader = shaders::gizmo::fragment::Shader::load(renderer.device.clone())
+			.expect("Failed to create fragment shader");
+
+		let gizmo = Gizmo::new(2.0);
+
+		let mut cube = Mesh::cube(1.0, Color::<f32>::WHITE);
+		cube.bake(renderer).expect("Failed to bake the cube");
+		let cube = Arc::new(cube);
+
+		let models: Vec<_> = (0..2000)
+			.into_iter()
+			.map(|_| {
+				let random_color = Color::<f32>::new(
+					rng.gen_range(0.3, 1.0),
+					rng.gen_range(0.3, 1.0),
+					rng.gen_range(0.3, 1.0),
+				);
+
+				let random_position = Vector3::new(
+					rng.gen_range(-5.0, 5.0),
+					rng.gen_range(-5.0, 5.0),
+					rng.gen_range(-5.0, 5.0),
+				);
+
+				let mut model = MeshInstance::new(cube.clone()).with_material(Material::new(random_color));
+				model.transform.translate(random_position);
+				model
+			})
+			.collect();
+
+		let instance_buffers: Vec<_> = (0..FRAMES_IN_FLIGHT).map(|_| {
+			CpuAccessibleBuffer::from_iter(
+				renderer.device.clone(),
+				BufferUsage::vertex_buffer(),
+				models.iter().map(MeshInstance::instance_data),
+			)
+			.unwrap()
+		}).collect();
 
-	let models_uniform_buffers: Vec<_> = models.iter().map(|m| {
-		let normal_matrix = m.transform.model_matrix().invert().unwrap().transpose();
+		let model_update_rules: Vec<_> = models.iter().map(|_| {
+			(
+				Rad(rng.gen_range(-std::f32::consts::FRAC_PI_4, std::f32::consts::FRAC_PI_4)),
+				Rad(rng.gen_range(-std::f32::consts::FRAC_PI_4, std::f32::consts::FRAC_PI_4)),
+				Rad(rng.gen_range(-std::f32::consts::FRAC_PI_4, std::f32::consts::FRAC_PI_4)),
+			)
+		}).collect();
 
-		let data = shaders::basic::vertex::ty::ModelData {
-			model_matrix: m.transform.model_matrix().into(),
-			normal_matrix: normal_matrix.into(),
-		};
+		let world_data_uniform_buffer = CpuBufferPool::<shaders::basic::vertex::ty::WorldData>::new(
+			renderer.device.clone(),
+			BufferUsage::uniform_buffer(),
+		);
 
-		CpuAccessibleBuffer::<shaders::basic::vertex::ty::ModelData>::from_data(renderer.device.clone(), BufferUsage::uniform_buffer(), data).unwrap()
-	}).collect();
+		let gizmo_uniform_buffer = CpuBufferPool::<shaders::gizmo::vertex::ty::Data>::new(
+			renderer.device.clone(),
+			BufferUsage::uniform_buffer(),
+		);
 
-	let model_update_rules: Vec<_> = models.iter().map(|_| {
-		(
-			Rad(rng.gen_range(-std::f32::consts::FRAC_PI_4, std::f32::consts::FRAC_PI_4)),
-			Rad(rng.gen_range(-std::f32::consts::FRAC_PI_4, std::f32::consts::FRAC_PI_4)),
-			Rad(rng.gen_range(-std::f32::consts::FRAC_PI_4, std::f32::consts::FRAC_PI_4)),
+		let pipeline = create_instanced_pipeline(
+			renderer.device.clone(),
+			&instanced_vertex_shader,
+			&basic_fragment_shader,
+			render_pass.clone(),
 		)
-	}).collect();
-
-	let world_data_uniform_buffer = CpuBufferPool::<shaders::basic::vertex::ty::WorldData>::new(
-		renderer.device.clone(),
-		BufferUsage::uniform_buffer(),
-	);
+		.unwrap();
 
-	let gizmo_uniform_buffer = CpuBufferPool::<shaders::gizmo::vertex::ty::Data>::new(
-		renderer.device.clone(),
-		BufferUsage::uniform_buffer(),
-	);
-
-	let render_pass = Arc::new(
-		vulkano::single_pass_renderpass!(renderer.device.clone(),
-		    attachments: {
-			color: {
-			    load: Clear,
-			    store: Store,
-			    format: Format::B8G8R8A8Unorm,
-			    samples: 1,
-			},
-			depth: {
-			    load: Clear,
-			    store: DontCare,
-			    format: Format::D16Unorm,
-			    samples: 1,
-			}
-		    },
-		    pass: {
-			color: [color],
-			depth_stencil: {depth}
-		    }
-		)
-		.unwrap(),
-	);
-
-	let mut pipeline = create_pipeline(
-		&basic_vertex_shader,
-		&basic_fragment_shader,
-		&renderer.state,
-		render_pass.clone(),
-		renderer.device.clone(),
-	);
-
-	let mut gizmo_pipeline = Arc::new(
-		GraphicsPipeline::start()
-			.line_list()
-			.line_width(renderer.config.line_width)
-			.vertex_input_single_buffer::<Vertex>()
-			.vertex_shader(gizmo_vertex_shader.main_entry_point(), ())
-			.fragment_shader(gizmo_fragment_shader.main_entry_point(), ())
-			.viewports_dynamic_scissors_irrelevant(1)
-			.viewports(std::iter::once(Viewport {
-				origin: [0.0, 0.0],
-				dimensions: renderer.state.dimensions,
-				depth_range: 0.0..1.0,
-			}))
-			.depth_stencil_simple_depth()
-			.render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
-			.build(renderer.device.clone())
-			.unwrap(),
-	);
-
-	let models_set: Vec<_> = models_uniform_buffers.iter().map(|ub| {
-		Arc::new(
-			PersistentDescriptorSet::start(pipeline.clone(), 0)
-				.add_buffer(ub.clone())
-				.unwrap()
-				.build()
-				.unwrap()
+		let gizmo_pipeline = create_gizmo_pipeline(
+			renderer.device.clone(),
+			&gizmo_vertex_shader,
+			&gizmo_fragment_shader,
+			render_pass.clone(),
+			renderer.config.line_width,
 		)
-	}).collect();
-
-	let mut pool = FixedSizeDescriptorSetsPool::new(pipeline.clone(), 0);
-
-	let mut framebuffers =
-		create_framebuffers(renderer.device.clone(), &images, render_pass.clone());
-
-	let mut fps_counter = FPSCounter::new();
-
-	let mut camera = CameraCenter::new();
-	camera.set_active(true);
+		.unwrap();
 
-	let light_position = Vector3::new(0.0, 10.0, 0.0);
+		let pool = FixedSizeDescriptorSetsPool::new(pipeline.clone(), 0);
 
-	let gizmo_vertex_buffer = {
-		let gizmo_transfer_buffer = CpuAccessibleBuffer::from_iter(
-			renderer.device.clone(),
-			BufferUsage::transfer_source(),
+		let gizmo_vertex_buffer = upload_to_device_local(
+			renderer,
+			BufferUsage::vertex_buffer(),
 			gizmo.vertices.into_iter(),
 		)
-		.unwrap();
+		.expect("Failed to upload the gizmo");
+
+		Self {
+			instanced_vertex_shader,
+			basic_fragment_shader,
+			gizmo_vertex_shader,
+			gizmo_fragment_shader,
+			render_pass,
+			pipeline,
+			gizmo_pipeline,
+			cube,
+			previous_transforms: models.iter().map(|m| m.transform.clone()).collect(),
+			models,
+			instance_buffers,
+			model_update_rules,
+			world_data_uniform_buffer,
+			gizmo_uniform_buffer,
+			pool,
+			gizmo_vertex_buffer,
+			light_position: Vector3::new(0.0, 10.0, 0.0),
+		}
+	}
 
-		let buffer: Arc<DeviceLocalBuffer<[Vertex]>> = DeviceLocalBuffer::array(
+	/// Rebuilds the pipelines, which take the line width from the configuration.
+	fn recreate_pipelines(&mut self, renderer: &Renderer) {
+		self.pipeline = create_instanced_pipeline(
 			renderer.device.clone(),
-			gizmo_transfer_buffer.len(),
-			BufferUsage::vertex_buffer_transfer_destination(),
-			vec![
-				renderer.graphics_queue.family(),
-				renderer.transfer_queue.family(),
-			],
+			&self.instanced_vertex_shader,
+			&self.basic_fragment_shader,
+			self.render_pass.clone(),
 		)
 		.unwrap();
 
-		let transfer_command = AutoCommandBufferBuilder::primary_one_time_submit(
+		self.gizmo_pipeline = create_gizmo_pipeline(
 			renderer.device.clone(),
-			renderer.transfer_queue.family(),
+			&self.gizmo_vertex_shader,
+			&self.gizmo_fragment_shader,
+			self.render_pass.clone(),
+			renderer.config.line_width,
 		)
-		.unwrap()
-		.copy_buffer(gizmo_transfer_buffer.clone(), buffer.clone())
-		.unwrap()
-		.build()
 		.unwrap();
+	}
 
-		transfer_command
-			.execute(renderer.transfer_queue.clone())
-			.unwrap()
-			.then_signal_fence_and_flush()
-			.unwrap()
-			.wait(None)
-			.unwrap();
-
-		buffer
-	};
-
-	let mut last_instant = std::time::Instant::now();
-
-	while renderer.state.is_running {
-		let frame_start = std::time::Instant::now();
-		let _elapsed = (std::time::Instant::now() - last_instant).as_secs_f32();
-		last_instant = std::time::Instant::now();
-		
-		for (i, model) in models.iter_mut().enumerate() {
-			model.transform.rotate(Vector3::unit_x(), model_update_rules[i].0 * _elapsed);
-			model.transform.rotate(Vector3::unit_y(), model_update_rules[i].1 * _elapsed);
-			model.transform.rotate(Vector3::unit_z(), model_update_rules[i].2 * _elapsed);
-		}
-
-		events_loop.poll_events(|event| {
-			handle_input(event, &mut renderer.state, &mut renderer.input, &mut camera);
-		});
-
-		if renderer.state.need_recreation {
-			let u_dimensions = [
-				renderer.state.dimensions[0] as u32,
-				renderer.state.dimensions[1] as u32,
-			];
-			let s = swapchain
-				.recreate_with_dimension(u_dimensions)
-				.expect("Failed to recreate swapchain");
-
-			swapchain = s.0;
-			images = s.1;
-
-			pipeline = create_pipeline(
-				&basic_vertex_shader,
-				&basic_fragment_shader,
-				&renderer.state,
-				render_pass.clone(),
-				renderer.device.clone(),
-			);
-
-			gizmo_pipeline = Arc::new(
-				GraphicsPipeline::start()
-					.line_list()
-					.line_width(renderer.config.line_width)
-					.vertex_input_single_buffer::<Vertex>()
-					.vertex_shader(gizmo_vertex_shader.main_entry_point(), ())
-					.fragment_shader(
-						gizmo_fragment_shader.main_entry_point(),
-						(),
-					)
-					.viewports_dynamic_scissors_irrelevant(1)
-					.viewports(std::iter::once(Viewport {
-						origin: [0.0, 0.0],
-						dimensions: renderer.state.dimensions,
-						depth_range: 0.0..1.0,
-					}))
-					.render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
-					.depth_stencil_simple_depth()
-					.build(renderer.device.clone())
-					.unwrap(),
-			);
-
-			framebuffers = create_framebuffers(
-				renderer.device.clone(),
-				&images,
-				render_pass.clone(),
-			);
-			renderer.state.need_recreation = false;
-		}
-
-		renderer.input.update();
-
-		if renderer.input.mouse_left_button_state == winit::ElementState::Pressed {
-			camera.update_pitch(renderer.input.mouse_movement.y as f32 * 0.01);
-			camera.update_yaw(-renderer.input.mouse_movement.x as f32 * 0.01);
+	fn update(&mut self, elapsed: f32) {
+		for (i, model) in self.models.iter_mut().enumerate() {
+			self.previous_transforms[i] = model.transform.clone();
+			model.transform.rotate(Vector3::unit_x(), self.model_update_rules[i].0 * elapsed);
+			model.transform.rotate(Vector3::unit_y(), self.model_update_rules[i].1 * elapsed);
+			model.transform.rotate(Vector3::unit_z(), self.model_update_rules[i].2 * elapsed);
 		}
+	}
 
+	/// Records the draw commands of the scene in the current subpass, with the model
+	/// transforms of `frame_index`.
+	fn draw(
+		&mut self,
+		renderer: &Renderer,
+		camera: &CameraCenter,
+		command_buffer_builder: AutoCommandBufferBuilder,
+		dynamic_state: &DynamicState,
+		frame_index: usize,
+	) -> AutoCommandBufferBuilder {
 		let uniform_gizmo_subbuffer = {
 			let uniform_data = shaders::gizmo::vertex::ty::Data {
 				model: Matrix4::from_scale(1.0).into(),
 				normal: Matrix4::from_scale(1.0).into(),
 				view: camera.view_matrix().into(),
 				proj: renderer.state.projection.into(),
-				light_position: light_position.into(),
+				light_position: self.light_position.into(),
 				_dummy0: [0; 4],
 				view_position: camera.position().into(),
 			};
 
-			gizmo_uniform_buffer.next(uniform_data).unwrap()
+			self.gizmo_uniform_buffer.next(uniform_data).unwrap()
 		};
 
 		let gizmo_set = Arc::new(
-			PersistentDescriptorSet::start(gizmo_pipeline.clone(), 0)
+			PersistentDescriptorSet::start(self.gizmo_pipeline.clone(), 0)
 				.add_buffer(uniform_gizmo_subbuffer)
 				.unwrap()
 				.build()
 				.unwrap(),
 		);
 
-		let (image_num, acquire_future) =
-			match vulkano::swapchain::acquire_next_image(swapchain.clone(), None) {
-				Ok(r) => r,
-				Err(vulkano::swapchain::AcquireError::OutOfDate) => {
-					continue;
-				}
-				Err(err) => panic!("{:?}", err),
-			};
-
-		let mut command_buffer_builder = AutoCommandBufferBuilder::primary_one_time_submit(
-			renderer.device.clone(),
-			renderer.graphics_queue.family(),
-		)
-		.unwrap()
-		.begin_render_pass(
-			framebuffers[image_num].clone(),
-			false,
-			vec![renderer.config.clear_color.into(), 1f32.into()],
-		)
-		.unwrap()
-		.draw(
-			gizmo_pipeline.clone(),
-			&DynamicState::none(),
-			gizmo_vertex_buffer.clone(),
-			gizmo_set.clone(),
-			(),
-		)
-		.unwrap();
+		let command_buffer_builder = command_buffer_builder
+			.draw(
+				self.gizmo_pipeline.clone(),
+				dynamic_state,
+				vec![self.gizmo_vertex_buffer.clone()],
+				gizmo_set.clone(),
+				(),
+			)
+			.unwrap();
 
 		let world_data_subbuffer = {
 			let uniform_data = shaders::basic::vertex::ty::WorldData {
 				view_matrix: camera.view_matrix().into(),
 				projection_matrix: renderer.state.projection.into(),
-				light_position: light_position.into(),
+				light_position: self.light_position.into(),
 				_dummy0: [0; 4],
 				view_position: camera.position().into(),
 			};
 
-			world_data_uniform_buffer.next(uniform_data).unwrap()
+			self.world_data_uniform_buffer.next(uniform_data).unwrap()
 		};
 
 		let world_descriptor_set = Arc::new(
-			pool.next()
+			self.pool.next()
 				.add_buffer(world_data_subbuffer)
 				.unwrap()
 				.build()
 				.unwrap(),
 		);
-		
+
 		let t = Timer::new();
 
-		for (i, model) in models.iter().enumerate() {
-			command_buffer_builder = model.render_with_sets(
-				command_buffer_builder,
-				pipeline.clone(),
-				(world_descriptor_set.clone(), models_set[i].clone())
-			);
-		}
+		let command_buffer_builder = self.cube.render_instanced(
+			command_buffer_builder,
+			self.pipeline.clone(),
+			dynamic_state,
+			self.instance_buffers[frame_index].clone(),
+			world_descriptor_set,
+		)
+		.unwrap();
 
 		println!("Took {} ns", t.stop().as_nanos());
 
-		let command_buffer = command_buffer_builder
-			.end_render_pass()
-			.unwrap()
-			.build()
-			.unwrap();
+		command_buffer_builder
+	}
 
-		match acquire_future.then_execute(renderer.graphics_queue.clone(), command_buffer) {
-			Ok(buffer_execute_future) => {
-				match buffer_execute_future
-					.then_swapchain_present(
-						renderer.graphics_queue.clone(),
-						swapchain.clone(),
-						image_num,
-					)
-					.then_signal_fence_and_flush()
-				{
-					Ok(_) => {}
-					Err(_) => {}
-				}
-			}
-			Err(_) => {}
-		};
-		for (i, buffer) in models_uniform_buffers.iter().enumerate() {
-			match buffer.write() {
-				Ok(mut write_lock) => {
-					let normal_matrix = models[i].transform.model_matrix().invert().unwrap().transpose();
-
-					let data = shaders::basic::vertex::ty::ModelData {
-						model_matrix: models[i].transform.model_matrix().into(),
-						normal_matrix: normal_matrix.into(),
-					};
-					*write_lock = data;
-				},
-				_ => {}
-			}
+	/// Copies the model transforms, `alpha` of the way from the previous update to the
+	/// last one, to the instance buffer of `frame_index`, which the GPU must be done with.
+	fn write_model_data(&self, frame_index: usize, alpha: f32) -> renderer::Result<()> {
+		let mut instances = self.instance_buffers[frame_index].write()?;
+		let transforms = self.models.iter().zip(&self.previous_transforms);
+		for ((model, previous), instance) in transforms.zip(instances.iter_mut()) {
+			*instance = InstanceData::new(&model.transform.interpolate(previous, alpha))
+				.with_color(model.material.color);
 		}
 
-		if let Some(mean_frame_duration) = fps_counter.update() {
-			surface.window()
-				.set_title(&format!("{} FPS", 1.0 / mean_frame_duration));
+		Ok(())
+	}
+}
+
+fn main() {
+	// The renderer reports the device, screenshots and recordings at the info level
+	env_logger::from_env(
+		env_logger::Env::default().default_filter_or("warn,renderer=info,renderer::validation=warn"),
+	)
+	.init();
+
+	let mut args: Vec<String> = std::env::args().skip(1).collect();
+	let print_config = take_flag(&mut args, "--print-config");
+	let device_info = take_device_info_flag(&mut args);
+	let record_output = take_value(&mut args, "--record").map(PathBuf::from);
+	let record_frames = take_value(&mut args, "--frames").map(|frames| {
+		match frames.parse::<u32>() {
+			Ok(count) if count > 0 => count,
+			_ => {
+				eprintln!("Invalid number of frames `{}`, expected a positive integer", frames);
+				std::process::exit(1);
+			}
 		}
+	});
+	// When recording, --headless only selects the mode and takes no output
+	let (headless, headless_output) = if record_output.is_some() {
+		(take_flag(&mut args, "--headless"), None)
+	} else {
+		let output = take_value(&mut args, "--headless").map(PathBuf::from);
+		(output.is_some(), output)
+	};
 
-		let frame_end = std::time::Instant::now();
-		if let Some(target) = target_frame_duration {
-			if let Some(sleep_duration) =
-				Duration::from_secs_f32(target).checked_sub(frame_end - frame_start)
-			{
-				std::thread::sleep(sleep_duration);
+	let config_builder = RendererConfigBuilder::start()
+		.with_optional_file("renderer.toml")
+		.with_env()
+		.with_args(args)
+		.unwrap_or_else(|e| {
+			eprintln!("{}", e);
+			std::process::exit(1);
+		});
+
+	let layered_config = config_builder.build().unwrap_or_else(|e| {
+		eprintln!("{}", e);
+		std::process::exit(1);
+	});
+
+	if print_config {
+		print!("{}", layered_config);
+		return;
+	}
+
+	if let Some(format) = device_info {
+		print_device_reports(format);
+		return;
+	}
+
+	let config = layered_config.config;
+	let result = match (record_output, headless_output) {
+		(Some(output), _) => {
+			let recording = Recording {
+				output,
+				frames: record_frames.unwrap_or(300),
+				fps: config.fps.unwrap_or(60.0),
+			};
+			// Seeded, so that recordings of the same configuration are identical
+			let app = CubesApp::new(0).with_orbit(0.5);
+			if headless {
+				renderer::record_headless(config, recording, app)
+			} else {
+				renderer::record(config, recording, app)
 			}
 		}
+		(None, Some(output)) => run_headless(config, &output),
+		(None, None) => renderer::run_with_watcher(
+			config,
+			Some(ConfigWatcher::new(config_builder)),
+			CubesApp::new(rand::random()),
+		),
+	};
+
+	if let Err(e) = result {
+		eprintln!("{}", e);
+		std::process::exit(1);
+	}
+}
+
+/// Renders a single frame of the scene offscreen and writes it to `output` as a PNG.
+fn run_headless(config: RendererConfig, output: &Path) -> renderer::Result<()> {
+	let mut renderer = Renderer::create_headless(config)?;
+
+	let format = select_readable_format(renderer.config.format);
+	let depth_format = renderer.select_depth_format()?;
+
+	let (width, height) = (renderer.config.width, renderer.config.height);
+	renderer.state.set_dimensions(width, height);
+
+	let target = OffscreenTarget::new(
+		renderer.device.clone(),
+		[width as u32, height as u32],
+		format,
+		depth_format,
+		renderer.config.msaa_samples,
+	)?;
+
+	let render_pass = create_render_pass(
+		renderer.device.clone(),
+		format,
+		depth_format,
+		renderer.config.msaa_samples,
+	)?;
+	let framebuffer = target.framebuffer(render_pass.clone())?;
+
+	// A fixed seed, so that the same configuration always gives the same image
+	let mut scene = Scene::new(&renderer, render_pass, &mut StdRng::seed_from_u64(0));
+
+	let camera = CameraCenter::new();
+
+	let command_buffer_builder = AutoCommandBufferBuilder::primary_one_time_submit(
+		renderer.device.clone(),
+		renderer.graphics_queue.family(),
+	)?
+	.begin_render_pass(framebuffer, false, renderer.clear_values())?;
+
+	let command_buffer_builder = scene
+		.draw(&renderer, &camera, command_buffer_builder, &renderer.state.dynamic_state(), 0)
+		.end_render_pass()?;
+
+	target
+		.copy_to_readback(command_buffer_builder)?
+		.build()?
+		.execute(renderer.graphics_queue.clone())?
+		.then_signal_fence_and_flush()?
+		.wait(None)?;
+
+	renderer.check_validation();
+
+	target.save_png(output)?;
+
+	println!("Frame written to {}", output.display());
+	Ok(())
+}
+
+/// The windowed demo: the scene seen from a camera orbiting around its center, which
+/// can be rotated by dragging the mouse and zoomed with the wheel. Space pauses the
+/// cubes.
+struct CubesApp {
+	// Created in `init`, once the render pass is known
+	scene: Option<Scene>,
+	camera: CameraCenter,
+	/// The seed of the cube placement and rotations.
+	seed: u64,
+	/// The speed at which the camera turns on its own, in radians per second.
+	orbit_speed: f32,
+}
+
+impl CubesApp {
+	fn new(seed: u64) -> Self {
+		let mut camera = CameraCenter::new();
+		camera.set_active(true);
+
+		Self {
+			scene: None,
+			camera,
+			seed,
+			orbit_speed: 0.0,
+		}
+	}
+
+	fn with_orbit(mut self, orbit_speed: f32) -> Self {
+		self.orbit_speed = orbit_speed;
+		self
 	}
 }
 
-fn handle_input(
-	event: Event,
-	state: &mut ApplicationState,
-	input: &mut Input,
-	camera: &mut CameraCenter,
-) {
-	match event {
-		Event::WindowEvent {
-			event: WindowEvent::CloseRequested,
-			..
-		} => state.is_running = false,
-		Event::WindowEvent {
-			event: WindowEvent::CursorMoved { position, .. },
-			..
-		} => {
-			input.new_mouse_position = Some(position);
+impl App for CubesApp {
+	fn init(
+		&mut self,
+		renderer: &mut Renderer,
+		render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+	) -> renderer::Result<()> {
+		self.scene = Some(Scene::new(renderer, render_pass, &mut StdRng::seed_from_u64(self.seed)));
+		Ok(())
+	}
+
+	fn update(&mut self, dt: f32, input: &Input, _clock: &mut Clock) {
+		if input.mouse_left_button_state == ElementState::Pressed {
+			self.camera.update_pitch(input.mouse_movement.y as f32 * 0.01);
+			self.camera.update_yaw(-input.mouse_movement.x as f32 * 0.01);
 		}
-		Event::WindowEvent {
-			event:
-				WindowEvent::MouseInput {
-					state,
-					button: winit::MouseButton::Left,
+
+		self.camera.update_yaw(self.orbit_speed * dt);
+	}
+
+	fn fixed_update(&mut self, dt: f32) {
+		self.scene.as_mut().unwrap().update(dt);
+	}
+
+	fn render(&mut self, context: &mut FrameContext) -> renderer::Result<()> {
+		let scene = self.scene.as_mut().unwrap();
+		let camera = &self.camera;
+		let (renderer, index) = (context.renderer, context.index);
+		let dynamic_state = context.dynamic_state.clone();
+
+		scene.write_model_data(index, context.alpha)?;
+		context.record(|builder| Ok(scene.draw(renderer, camera, builder, &dynamic_state, index)))
+	}
+
+	fn on_event(&mut self, event: &Event, clock: &mut Clock) {
+		let event = match event {
+			Event::WindowEvent { event, .. } => event,
+			_ => return,
+		};
+
+		match *event {
+			WindowEvent::MouseWheel { delta, .. } => {
+				let y = match delta {
+					winit::MouseScrollDelta::LineDelta(_, y) => y as f64,
+					winit::MouseScrollDelta::PixelDelta(LogicalPosition { y, .. }) => y,
+				};
+				self.camera.update_radius(-y as f32 * 0.1);
+			}
+			WindowEvent::KeyboardInput {
+				input: KeyboardInput {
+					state: ElementState::Pressed,
+					virtual_keycode: Some(VirtualKeyCode::Space),
 					..
 				},
-			..
-		} => {
-			input.mouse_left_button_state = state;
+				..
+			} => clock.set_paused(!clock.is_paused()),
+			_ => {}
 		}
-		Event::WindowEvent {
-			event: WindowEvent::MouseWheel { delta, .. },
-			..
-		} => {
-			let (_, y): (f64, f64) = match delta {
-				winit::MouseScrollDelta::LineDelta(x, y) => (x as f64, y as f64),
-				winit::MouseScrollDelta::PixelDelta(LogicalPosition { x, y }) => {
-					(x, y)
-				}
-			};
-			camera.update_radius(-y as f32 * 0.1);
-		}
-		Event::WindowEvent {
-			event: WindowEvent::Resized(LogicalSize { width, height }),
-			..
-		} => {
-			state.set_dimensions(width as f32, height as f32);
-			state.need_recreation = true;
+	}
+
+	fn on_recreated(&mut self, renderer: &Renderer) -> renderer::Result<()> {
+		self.scene.as_mut().unwrap().recreate_pipelines(renderer);
+		Ok(())
+	}
+}
+
+/// Removes a flag taking no value from the arguments, returning whether it was present.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+	let len = args.len();
+	args.retain(|arg| arg != flag);
+	args.len() != len
+}
+
+/// Removes `<flag> <value>` or `<flag>=<value>` from the arguments, returning the value.
+fn take_value(args: &mut Vec<String>, flag: &str) -> Option<String> {
+	let prefix = format!("{}=", flag);
+	let index = args
+		.iter()
+		.position(|arg| arg == flag || arg.starts_with(&prefix))?;
+
+	let arg = args.remove(index);
+	if arg != flag {
+		return Some(arg[prefix.len()..].to_string());
+	}
+
+	if index < args.len() {
+		Some(args.remove(index))
+	} else {
+		eprintln!("Missing value for {}", flag);
+		std::process::exit(1);
+	}
+}
+
+#[derive(Clone, Copy)]
+enum ReportFormat {
+	Json,
+	Toml,
+}
+
+/// Removes `--device-info` or `--device-info=<json|toml>` from the arguments.
+fn take_device_info_flag(args: &mut Vec<String>) -> Option<ReportFormat> {
+	let index = args
+		.iter()
+		.position(|arg| arg == "--device-info" || arg.starts_with("--device-info="))?;
+
+	let format = match args.remove(index).trim_start_matches("--device-info") {
+		"" | "=json" => ReportFormat::Json,
+		"=toml" => ReportFormat::Toml,
+		other => {
+			eprintln!(
+				"Unknown device report format `{}`, expected json or toml",
+				other.trim_start_matches('=')
+			);
+			std::process::exit(1);
 		}
-		_ => {}
+	};
+
+	Some(format)
+}
+
+/// Prints a report for every physical device, including what they support when
+/// presenting to a window of this platform.
+fn print_device_reports(format: ReportFormat) {
+	let instance = Renderer::create_instance().unwrap_or_else(|e| {
+		eprintln!("{}", e);
+		std::process::exit(1);
+	});
+	let events_loop = EventsLoop::new();
+	let surface = WindowBuilder::new()
+		.with_visibility(false)
+		.build_vk_surface(&events_loop, instance.clone())
+		.ok();
+
+	let reports: Vec<DeviceReport> = PhysicalDevice::enumerate(&instance)
+		.map(|physical_device| {
+			let report = DeviceReport::new(&physical_device);
+			match &surface {
+				Some(surface) => report.with_surface(&physical_device, surface),
+				None => report,
+			}
+		})
+		.collect();
+
+	match format {
+		ReportFormat::Json => println!("{}", reports_to_json(&reports)),
+		ReportFormat::Toml => match reports_to_toml(&reports) {
+			Ok(toml) => println!("{}", toml),
+			Err(e) => {
+				eprintln!("Failed to write the device reports as TOML: {}", e);
+				std::process::exit(1);
+			}
+		},
 	}
 }
@@ -0,0 +1,170 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use image::RgbaImage;
+use log::warn;
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer};
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::device::Device;
+use vulkano::format::Format;
+use vulkano::framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract};
+use vulkano::image::{AttachmentImage, ImageAccess, ImageUsage};
+
+use super::{Error, Result};
+
+/// Color formats whose pixels can be read back into an RGBA8 image.
+pub const READABLE_FORMATS: &[Format] = &[
+    Format::B8G8R8A8Unorm,
+    Format::B8G8R8A8Srgb,
+    Format::R8G8B8A8Unorm,
+    Format::R8G8B8A8Srgb,
+];
+
+/// `format` if it is one of `READABLE_FORMATS`, `B8G8R8A8Unorm` otherwise.
+pub fn select_readable_format(format: Format) -> Format {
+    if READABLE_FORMATS.contains(&format) {
+        return format;
+    }
+
+    warn!(
+        "Format {:?} can't be read back, falling back to {:?}",
+        format,
+        Format::B8G8R8A8Unorm
+    );
+    Format::B8G8R8A8Unorm
+}
+
+/// A render target living in device memory instead of a swapchain, along with the
+/// host-visible buffer its color attachment is copied to for readback.
+pub struct OffscreenTarget {
+    color: Arc<AttachmentImage>,
+    /// Resolved into `color` when rendering with MSAA.
+    multisampled_color: Option<Arc<AttachmentImage>>,
+    depth: Arc<AttachmentImage>,
+    readback: Arc<CpuAccessibleBuffer<[u8]>>,
+}
+
+impl OffscreenTarget {
+    /// `format` has to be one of `READABLE_FORMATS`. `samples` must be supported by the
+    /// device, see `format::select_sample_count`.
+    pub fn new(
+        device: Arc<Device>,
+        dimensions: [u32; 2],
+        format: Format,
+        depth_format: Format,
+        samples: u32,
+    ) -> Result<Self> {
+        if !READABLE_FORMATS.contains(&format) {
+            return Err(Error::UnreadableFormat(format));
+        }
+
+        let color_usage = ImageUsage {
+            color_attachment: true,
+            transfer_source: true,
+            ..ImageUsage::none()
+        };
+
+        let color = AttachmentImage::with_usage(device.clone(), dimensions, format, color_usage)?;
+        let multisampled_color = if samples > 1 {
+            Some(AttachmentImage::transient_multisampled(
+                device.clone(),
+                dimensions,
+                samples,
+                format,
+            )?)
+        } else {
+            None
+        };
+        let depth = AttachmentImage::transient_multisampled(
+            device.clone(),
+            dimensions,
+            samples,
+            depth_format,
+        )?;
+
+        let size = (dimensions[0] * dimensions[1] * 4) as usize;
+        let readback = CpuAccessibleBuffer::from_iter(
+            device,
+            BufferUsage::transfer_destination(),
+            (0..size).map(|_| 0u8),
+        )?;
+
+        Ok(Self {
+            color,
+            multisampled_color,
+            depth,
+            readback,
+        })
+    }
+
+    pub fn dimensions(&self) -> [u32; 2] {
+        self.color.dimensions().width_height()
+    }
+
+    pub fn format(&self) -> Format {
+        self.color.format()
+    }
+
+    /// A framebuffer for the render passes of `resources::pipelines::create_render_pass`,
+    /// created with the same sample count as the target.
+    pub fn framebuffer(
+        &self,
+        render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    ) -> Result<Arc<dyn FramebufferAbstract + Send + Sync>> {
+        Ok(match &self.multisampled_color {
+            Some(multisampled_color) => Arc::new(
+                Framebuffer::start(render_pass)
+                    .add(multisampled_color.clone())?
+                    .add(self.depth.clone())?
+                    .add(self.color.clone())?
+                    .build()?,
+            ),
+            None => Arc::new(
+                Framebuffer::start(render_pass)
+                    .add(self.color.clone())?
+                    .add(self.depth.clone())?
+                    .build()?,
+            ),
+        })
+    }
+
+    /// Records the copy of the color attachment into the readback buffer. It must be
+    /// recorded after the render pass and have completed before calling `read`.
+    pub fn copy_to_readback(
+        &self,
+        command_buffer_builder: AutoCommandBufferBuilder,
+    ) -> Result<AutoCommandBufferBuilder> {
+        Ok(command_buffer_builder
+            .copy_image_to_buffer(self.color.clone(), self.readback.clone())?)
+    }
+
+    /// The content of the readback buffer as an RGBA image.
+    pub fn read(&self) -> Result<RgbaImage> {
+        let [width, height] = self.dimensions();
+        let pixels = self.readback.read()?;
+
+        // The readback buffer is sized from the dimensions of the target
+        RgbaImage::from_raw(width, height, to_rgba8(self.format(), &pixels))
+            .ok_or(Error::ReadbackSizeMismatch)
+    }
+
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        Ok(self.read()?.save(path)?)
+    }
+}
+
+/// Converts tightly packed pixels of one of `READABLE_FORMATS` to RGBA8. The bytes are
+/// kept as stored, so sRGB formats give sRGB-encoded images, as PNG expects.
+pub fn to_rgba8(format: Format, pixels: &[u8]) -> Vec<u8> {
+    match format {
+        Format::R8G8B8A8Unorm | Format::R8G8B8A8Srgb => pixels.to_vec(),
+        Format::B8G8R8A8Unorm | Format::B8G8R8A8Srgb => {
+            let mut rgba = pixels.to_vec();
+            for pixel in rgba.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+            rgba
+        }
+        _ => panic!("Format {:?} can't be read back", format),
+    }
+}
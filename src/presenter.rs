@@ -0,0 +1,466 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use image::RgbaImage;
+use log::{error, info, warn};
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBuffer};
+use vulkano::device::Device;
+use vulkano::format::Format;
+use vulkano::framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract};
+use vulkano::image::attachment::AttachmentImage;
+use vulkano::image::swapchain::SwapchainImage;
+use vulkano::image::ImageAccess;
+use vulkano::swapchain::{
+    AcquireError, PresentMode, Surface, SurfaceTransform, Swapchain, SwapchainAcquireFuture,
+};
+use vulkano::sync::{self, FenceSignalFuture, FlushError, GpuFuture};
+use winit::Window;
+
+use super::format::select_surface_format;
+use super::offscreen::{to_rgba8, READABLE_FORMATS};
+use super::{Error, Renderer, Result};
+
+/// How many frames the CPU can record while the GPU is still busy with previous ones.
+pub const FRAMES_IN_FLIGHT: usize = 2;
+
+type SwapchainImages = Vec<Arc<SwapchainImage<Window>>>;
+pub type FrameFence = Arc<FenceSignalFuture<Box<dyn GpuFuture + Send + Sync>>>;
+
+/// The fences of the frames submitted without waiting, up to `FRAMES_IN_FLIGHT`. Each
+/// frame is chained after the previous one.
+///
+/// The resources a frame uses stay locked by vulkano until its fence is waited for or
+/// found signaled: waiting for the queue to be idle is not enough to write to them.
+pub struct FramesInFlight {
+    fences: Vec<Option<FrameFence>>,
+    previous_frame_end: Option<FrameFence>,
+    index: usize,
+}
+
+impl FramesInFlight {
+    pub fn new() -> Self {
+        Self {
+            fences: vec![None; FRAMES_IN_FLIGHT],
+            previous_frame_end: None,
+            index: 0,
+        }
+    }
+
+    /// Waits for the frame that last used the next index and returns that index, whose
+    /// resources can then be written to.
+    pub fn begin_frame(&mut self) -> Result<usize> {
+        if let Some(previous_frame_end) = &mut self.previous_frame_end {
+            previous_frame_end.cleanup_finished();
+        }
+
+        if let Some(fence) = &self.fences[self.index] {
+            fence.wait(None)?;
+        }
+
+        Ok(self.index)
+    }
+
+    /// The future the next frame must be executed after.
+    pub fn previous_frame_end(&mut self, device: &Arc<Device>) -> Box<dyn GpuFuture + Send + Sync> {
+        match self.previous_frame_end.take() {
+            Some(fence) => Box::new(fence),
+            None => Box::new(sync::now(device.clone())),
+        }
+    }
+
+    /// Tracks the frame of `index` that was just submitted. Without a fence, the caller
+    /// must have waited for the queue to be idle.
+    pub fn end_frame(&mut self, index: usize, fence: Option<FrameFence>) {
+        self.fences[index] = fence.clone();
+        self.previous_frame_end = fence;
+        self.index = (index + 1) % FRAMES_IN_FLIGHT;
+    }
+
+    /// Waits for every frame in flight and releases what they locked, so that the
+    /// resources of any index can be written to, e.g. to render offscreen.
+    pub fn wait_idle(&mut self) -> Result<()> {
+        for fence in self.fences.iter_mut().filter_map(Option::take) {
+            fence.wait(None)?;
+        }
+        if let Some(fence) = self.previous_frame_end.take() {
+            fence.wait(None)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for FramesInFlight {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Owns the swapchain of a window and the framebuffers rendering into its images.
+///
+/// The swapchain is recreated when `ApplicationState::need_recreation` is set, which
+/// happens when the window is resized or the swapchain is out of date.
+///
+/// Up to `FRAMES_IN_FLIGHT` frames are submitted without waiting, see `FramesInFlight`.
+/// `begin_frame` waits for the frame that last used the same `Frame::index`, so the
+/// resources indexed by it can be written to.
+pub struct Presenter {
+    surface: Arc<Surface<Window>>,
+    swapchain: Arc<Swapchain<Window>>,
+    images: SwapchainImages,
+    framebuffers: Vec<Arc<dyn FramebufferAbstract + Send + Sync>>,
+    render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    depth_format: Format,
+    recreated: bool,
+    frames: FramesInFlight,
+}
+
+/// An acquired swapchain image, to be drawn into then handed back to
+/// `Presenter::end_frame`.
+pub struct Frame {
+    /// In `0..FRAMES_IN_FLIGHT`, to select the per-frame resources. The GPU is done
+    /// with the ones of this index.
+    pub index: usize,
+    pub image_num: usize,
+    pub framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    /// Whether the swapchain was created or recreated since the previous frame, in
+    /// which case the resources matching its dimensions must be rebuilt.
+    pub recreated: bool,
+    acquire_future: SwapchainAcquireFuture<Window>,
+}
+
+impl Presenter {
+    /// `render_pass` must come from `resources::pipelines::create_render_pass`, with the
+    /// format from `Presenter::select_format`, `depth_format` and the MSAA sample count
+    /// of the renderer configuration.
+    pub fn new(
+        renderer: &mut Renderer,
+        surface: Arc<Surface<Window>>,
+        render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        depth_format: Format,
+    ) -> Result<Self> {
+        let (swapchain, images) = create_swapchain(renderer, &surface, None)?;
+        let framebuffers = create_framebuffers(renderer, &images, &render_pass, depth_format)?;
+
+        Ok(Self {
+            surface,
+            swapchain,
+            images,
+            framebuffers,
+            render_pass,
+            depth_format,
+            recreated: true,
+            frames: FramesInFlight::new(),
+        })
+    }
+
+    /// The format the swapchain of `surface` will be created with, in the configured
+    /// color space, falling back from the configured format when the surface doesn't
+    /// support it.
+    pub fn select_format(renderer: &Renderer, surface: &Surface<Window>) -> Result<Format> {
+        let color_space = renderer.config.color_space;
+        let caps = surface.capabilities(renderer.device.physical_device())?;
+        select_surface_format(&caps.supported_formats, renderer.config.format, color_space)
+            .ok_or(Error::NoSurfaceFormat(color_space))
+    }
+
+    pub fn surface(&self) -> &Arc<Surface<Window>> {
+        &self.surface
+    }
+
+    pub fn swapchain(&self) -> &Arc<Swapchain<Window>> {
+        &self.swapchain
+    }
+
+    pub fn format(&self) -> Format {
+        self.swapchain.format()
+    }
+
+    pub fn images(&self) -> &[Arc<SwapchainImage<Window>>] {
+        &self.images
+    }
+
+    pub fn frames_in_flight(&mut self) -> &mut FramesInFlight {
+        &mut self.frames
+    }
+
+    /// Waits for every presented frame, see `FramesInFlight::wait_idle`.
+    pub fn wait_idle(&mut self) -> Result<()> {
+        self.frames.wait_idle()
+    }
+
+    /// Whether the swapchain images can be copied to the CPU, which screenshots need.
+    /// It depends on the usages the surface supports and on the format.
+    pub fn can_capture(&self) -> bool {
+        ImageAccess::inner(&self.images[0])
+            .image
+            .usage_transfer_source()
+            && READABLE_FORMATS.contains(&self.format())
+    }
+
+    /// Recreates the swapchain and its framebuffers, updating the dimensions of the
+    /// renderer state.
+    pub fn recreate(&mut self, renderer: &mut Renderer) -> Result<()> {
+        let (swapchain, images) = create_swapchain(renderer, &self.surface, Some(&self.swapchain))?;
+        self.framebuffers =
+            create_framebuffers(renderer, &images, &self.render_pass, self.depth_format)?;
+        self.swapchain = swapchain;
+        self.images = images;
+        self.recreated = true;
+        renderer.state.need_recreation = false;
+        Ok(())
+    }
+
+    /// Acquires the next image, recreating the swapchain first if needed. Returns
+    /// `None` when the window is minimized or the swapchain went out of date during
+    /// the acquisition, in which case the frame should be skipped.
+    pub fn begin_frame(&mut self, renderer: &mut Renderer) -> Result<Option<Frame>> {
+        let index = self.frames.begin_frame()?;
+
+        // Set on resize and when the swapchain went out of date, which is the only time
+        // the extent of the surface needs to be queried
+        if renderer.state.need_recreation {
+            let current_extent = self
+                .surface
+                .capabilities(renderer.device.physical_device())?
+                .current_extent;
+
+            // A minimized window has no extent to create a swapchain with, the request
+            // is kept until it is restored
+            if let Some([0, _]) | Some([_, 0]) = current_extent {
+                return Ok(None);
+            }
+
+            self.recreate(renderer)?;
+        }
+
+        match vulkano::swapchain::acquire_next_image(self.swapchain.clone(), None) {
+            Ok((image_num, acquire_future)) => Ok(Some(Frame {
+                index,
+                image_num,
+                framebuffer: self.framebuffers[image_num].clone(),
+                recreated: std::mem::replace(&mut self.recreated, false),
+                acquire_future,
+            })),
+            Err(AcquireError::OutOfDate) => {
+                renderer.state.need_recreation = true;
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Executes `command_buffer` once the image of `frame` is acquired and the previous
+    /// frame is submitted, then presents it without waiting for the GPU. An out of date
+    /// swapchain is recreated on the next `begin_frame`.
+    ///
+    /// When a screenshot was requested with `Renderer::capture_screenshot` and
+    /// `can_capture` is true, the image is also copied to the CPU and saved, waiting
+    /// for the GPU to finish the frame. Otherwise the request is left pending.
+    pub fn end_frame<Cb>(
+        &mut self,
+        renderer: &mut Renderer,
+        frame: Frame,
+        command_buffer: Cb,
+    ) -> Result<()>
+    where
+        Cb: CommandBuffer + Send + Sync + 'static,
+    {
+        let previous_frame_end = self.frames.previous_frame_end(&renderer.device);
+
+        let screenshot = if self.can_capture() {
+            renderer.take_screenshot_request()
+        } else {
+            None
+        };
+
+        let rendered_future = previous_frame_end
+            .join(frame.acquire_future)
+            .then_execute(renderer.graphics_queue.clone(), command_buffer)?;
+
+        let (rendered_future, readback): (Box<dyn GpuFuture + Send + Sync>, _) = match screenshot {
+            Some(path) => {
+                let image = self.images[frame.image_num].clone();
+                let [width, height] = self.swapchain.dimensions();
+                let readback = CpuAccessibleBuffer::from_iter(
+                    renderer.device.clone(),
+                    BufferUsage::transfer_destination(),
+                    (0..width * height * 4).map(|_| 0u8),
+                )?;
+                let copy_command_buffer = AutoCommandBufferBuilder::primary_one_time_submit(
+                    renderer.device.clone(),
+                    renderer.graphics_queue.family(),
+                )?
+                .copy_image_to_buffer(image, readback.clone())?
+                .build()?;
+
+                let future = rendered_future
+                    .then_execute(renderer.graphics_queue.clone(), copy_command_buffer)?;
+                (Box::new(future), Some((path, readback)))
+            }
+            None => (Box::new(rendered_future), None),
+        };
+
+        let present_future: Box<dyn GpuFuture + Send + Sync> =
+            Box::new(rendered_future.then_swapchain_present(
+                renderer.graphics_queue.clone(),
+                self.swapchain.clone(),
+                frame.image_num,
+            ));
+
+        let fence = match present_future.then_signal_fence_and_flush() {
+            Ok(fence) => Some(Arc::new(fence)),
+            Err(FlushError::OutOfDate) => {
+                // Without a fence to track the frame, wait for it before reusing its index
+                renderer.graphics_queue.wait()?;
+                renderer.state.need_recreation = true;
+                None
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        if let Some((path, readback)) = readback {
+            match &fence {
+                Some(fence) => fence.wait(None)?,
+                None => renderer.graphics_queue.wait()?,
+            }
+
+            let [width, height] = self.swapchain.dimensions();
+            let pixels = readback.read()?;
+            let result = RgbaImage::from_raw(width, height, to_rgba8(self.format(), &pixels))
+                .ok_or(Error::ReadbackSizeMismatch)
+                .and_then(|image| Ok(image.save(&path)?));
+            report_screenshot(&path, result);
+        }
+
+        self.frames.end_frame(frame.index, fence);
+        Ok(())
+    }
+}
+
+/// A screenshot that can't be written isn't worth stopping the application for.
+pub(crate) fn report_screenshot<E: std::fmt::Display>(
+    path: &Path,
+    result: std::result::Result<(), E>,
+) {
+    match result {
+        Ok(()) => info!("Screenshot saved to {}", path.display()),
+        Err(e) => error!("Failed to save the screenshot to {}: {}", path.display(), e),
+    }
+}
+
+fn create_swapchain(
+    renderer: &mut Renderer,
+    surface: &Arc<Surface<Window>>,
+    old_swapchain: Option<&Arc<Swapchain<Window>>>,
+) -> Result<(Arc<Swapchain<Window>>, SwapchainImages)> {
+    let config = &renderer.config;
+    let caps = surface.capabilities(renderer.device.physical_device())?;
+
+    // Some platforms let the swapchain decide of the extent of the surface
+    let [width, height] = caps
+        .current_extent
+        .unwrap_or([config.width as u32, config.height as u32]);
+    let alpha = caps
+        .supported_composite_alpha
+        .iter()
+        .next()
+        .ok_or(Error::NoCompositeAlpha)?;
+
+    let format = Presenter::select_format(renderer, surface)?;
+    if format != config.format {
+        warn!(
+            "Format {:?} is not supported by the surface, falling back to {:?}",
+            config.format, format
+        );
+    }
+
+    let present_mode = if caps.present_modes.supports(config.present_mode) {
+        config.present_mode
+    } else {
+        warn!(
+            "Present mode {:?} is not supported by the surface, falling back to {:?}",
+            config.present_mode,
+            PresentMode::Fifo
+        );
+        PresentMode::Fifo
+    };
+
+    let image_count = match config.image_count {
+        Some(count) => {
+            let max_image_count = caps.max_image_count.unwrap_or(u32::MAX);
+            count.max(caps.min_image_count).min(max_image_count)
+        }
+        None => caps.min_image_count,
+    };
+
+    let (swapchain, images) = Swapchain::new(
+        renderer.device.clone(),
+        surface.clone(),
+        image_count,
+        format,
+        [width, height],
+        1,
+        caps.supported_usage_flags,
+        &renderer.graphics_queue,
+        SurfaceTransform::Identity,
+        alpha,
+        present_mode,
+        true,
+        old_swapchain,
+    )?;
+
+    renderer.state.set_dimensions(width as f32, height as f32);
+
+    Ok((swapchain, images))
+}
+
+fn create_framebuffers(
+    renderer: &Renderer,
+    images: &[Arc<SwapchainImage<Window>>],
+    render_pass: &Arc<dyn RenderPassAbstract + Send + Sync>,
+    depth_format: Format,
+) -> Result<Vec<Arc<dyn FramebufferAbstract + Send + Sync>>> {
+    let dimensions = images[0].swapchain().dimensions();
+    let samples = renderer.config.msaa_samples;
+    let depth_buffer = AttachmentImage::transient_multisampled(
+        renderer.device.clone(),
+        dimensions,
+        samples,
+        depth_format,
+    )?;
+
+    // The swapchain images become the resolve attachments of a multisampled color image
+    let color_buffer = if samples > 1 {
+        Some(AttachmentImage::transient_multisampled(
+            renderer.device.clone(),
+            dimensions,
+            samples,
+            images[0].swapchain().format(),
+        )?)
+    } else {
+        None
+    };
+
+    images
+        .iter()
+        .map(|image| {
+            let framebuffer: Arc<dyn FramebufferAbstract + Send + Sync> = match &color_buffer {
+                Some(color_buffer) => Arc::new(
+                    Framebuffer::start(render_pass.clone())
+                        .add(color_buffer.clone())?
+                        .add(depth_buffer.clone())?
+                        .add(image.clone())?
+                        .build()?,
+                ),
+                None => Arc::new(
+                    Framebuffer::start(render_pass.clone())
+                        .add(image.clone())?
+                        .add(depth_buffer.clone())?
+                        .build()?,
+                ),
+            };
+            Ok(framebuffer)
+        })
+        .collect()
+}
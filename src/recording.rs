@@ -0,0 +1,197 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use image::RgbaImage;
+
+use super::Result;
+
+/// What `app::record` and `app::record_headless` render: `frames` frames, each one
+/// advancing the clock by exactly `1 / fps` seconds, however long it takes to render.
+#[derive(Debug, Clone)]
+pub struct Recording {
+    /// A `.y4m` file receives a raw video stream; any other path is a directory
+    /// filled with `00000.png`, `00001.png`...
+    pub output: PathBuf,
+    pub frames: u32,
+    pub fps: f32,
+}
+
+impl Recording {
+    pub fn is_y4m(&self) -> bool {
+        match self.output.extension() {
+            Some(extension) => extension.eq_ignore_ascii_case("y4m"),
+            None => false,
+        }
+    }
+}
+
+enum Output {
+    PngSequence(PathBuf),
+    Y4m(BufWriter<File>),
+}
+
+/// Writes the frames of a `Recording`, which must all have the same dimensions.
+pub struct Recorder {
+    output: Output,
+    dimensions: [u32; 2],
+    frames_written: u32,
+}
+
+impl Recorder {
+    pub fn new(recording: &Recording, dimensions: [u32; 2]) -> Result<Self> {
+        let output = if recording.is_y4m() {
+            let mut writer = BufWriter::new(File::create(&recording.output)?);
+            let (numerator, denominator) = frame_rate_ratio(recording.fps);
+            // 4:4:4 chroma, so that thin lines keep their color
+            writeln!(
+                writer,
+                "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444",
+                dimensions[0], dimensions[1], numerator, denominator
+            )?;
+            Output::Y4m(writer)
+        } else {
+            std::fs::create_dir_all(&recording.output)?;
+            Output::PngSequence(recording.output.clone())
+        };
+
+        Ok(Self {
+            output,
+            dimensions,
+            frames_written: 0,
+        })
+    }
+
+    pub fn frames_written(&self) -> u32 {
+        self.frames_written
+    }
+
+    pub fn write_frame(&mut self, image: &RgbaImage) -> Result<()> {
+        assert_eq!(
+            image.dimensions(),
+            (self.dimensions[0], self.dimensions[1]),
+            "All the frames of a recording must have the same dimensions"
+        );
+
+        match &mut self.output {
+            Output::PngSequence(directory) => {
+                image.save(directory.join(format!("{:05}.png", self.frames_written)))?
+            }
+            Output::Y4m(writer) => {
+                writer.write_all(b"FRAME\n")?;
+                writer.write_all(&to_ycbcr444(image))?;
+            }
+        }
+
+        self.frames_written += 1;
+        Ok(())
+    }
+
+    /// Flushes the Y4M stream. Dropping the recorder does it too, ignoring errors.
+    pub fn finish(self) -> Result<()> {
+        if let Output::Y4m(mut writer) = self.output {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// `fps` as a ratio, exact for integer and NTSC-like rates such as 29.97.
+fn frame_rate_ratio(fps: f32) -> (u32, u32) {
+    if fps.fract() == 0.0 {
+        (fps as u32, 1)
+    } else {
+        ((fps * 1000.0).round() as u32, 1000)
+    }
+}
+
+/// The planes of a Y4M frame: Y, then Cb, then Cr, with the studio range BT.601
+/// coefficients that players assume for Y4M.
+fn to_ycbcr444(image: &RgbaImage) -> Vec<u8> {
+    let pixel_count = (image.width() * image.height()) as usize;
+    let mut planes = vec![0u8; pixel_count * 3];
+    let (y_plane, chroma_planes) = planes.split_at_mut(pixel_count);
+    let (cb_plane, cr_plane) = chroma_planes.split_at_mut(pixel_count);
+
+    for (i, pixel) in image.pixels().enumerate() {
+        let [r, g, b] = [
+            pixel[0] as f32 / 255.0,
+            pixel[1] as f32 / 255.0,
+            pixel[2] as f32 / 255.0,
+        ];
+
+        y_plane[i] = (16.0 + 65.481 * r + 128.553 * g + 24.966 * b).round() as u8;
+        cb_plane[i] = (128.0 - 37.797 * r - 74.203 * g + 112.0 * b).round() as u8;
+        cr_plane[i] = (128.0 + 112.0 * r - 93.786 * g - 18.214 * b).round() as u8;
+    }
+
+    planes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::Rgba;
+
+    #[test]
+    fn frame_rates_as_ratios() {
+        assert_eq!(frame_rate_ratio(30.0), (30, 1));
+        assert_eq!(frame_rate_ratio(60.0), (60, 1));
+        assert_eq!(frame_rate_ratio(29.97), (29970, 1000));
+        assert_eq!(frame_rate_ratio(23.976), (23976, 1000));
+    }
+
+    #[test]
+    fn known_colors_in_studio_range() {
+        let colors = [
+            [0, 0, 0],
+            [255, 255, 255],
+            [255, 0, 0],
+            [0, 255, 0],
+            [0, 0, 255],
+        ];
+        let mut image = RgbaImage::new(colors.len() as u32, 1);
+        for (x, [r, g, b]) in colors.iter().enumerate() {
+            image.put_pixel(x as u32, 0, Rgba([*r, *g, *b, 255]));
+        }
+
+        let planes = to_ycbcr444(&image);
+        let (y, chroma) = planes.split_at(colors.len());
+        let (cb, cr) = chroma.split_at(colors.len());
+
+        assert_eq!(y, &[16, 235, 81, 145, 41]);
+        assert_eq!(cb, &[128, 128, 90, 54, 240]);
+        assert_eq!(cr, &[128, 128, 240, 34, 110]);
+    }
+
+    #[test]
+    fn y4m_header_and_frames() {
+        let output =
+            std::env::temp_dir().join(format!("renderer-{}-recording.y4m", std::process::id()));
+        let recording = Recording {
+            output: output.clone(),
+            frames: 2,
+            fps: 29.97,
+        };
+        assert!(recording.is_y4m());
+
+        let image = RgbaImage::from_pixel(2, 1, Rgba([255, 255, 255, 255]));
+        let mut recorder = Recorder::new(&recording, [2, 1]).unwrap();
+        recorder.write_frame(&image).unwrap();
+        recorder.write_frame(&image).unwrap();
+        assert_eq!(recorder.frames_written(), 2);
+        recorder.finish().unwrap();
+
+        let contents = std::fs::read(&output).unwrap();
+        std::fs::remove_file(&output).unwrap();
+
+        let header = b"YUV4MPEG2 W2 H1 F29970:1000 Ip A1:1 C444\n";
+        let frame = b"FRAME\n\xeb\xeb\x80\x80\x80\x80";
+        assert_eq!(&contents[..header.len()], &header[..]);
+        assert_eq!(
+            &contents[header.len()..],
+            &[&frame[..], &frame[..]].concat()[..]
+        );
+    }
+}
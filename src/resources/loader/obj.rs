@@ -1,17 +1,31 @@
 use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
 use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
+use cgmath::Deg;
+
 use super::super::super::vertex::Vertex;
-use super::super::model::{FromBuffers, Model};
+use super::super::mesh::ops;
+use super::super::model::{FromBuffers, Mesh};
+
+/// When a file has no normals, the faces meeting at a sharper angle keep distinct
+/// normals, see `ops::compute_smooth_normals`.
+const CREASE_ANGLE: Deg<f32> = Deg(60.0);
 
 pub struct ObjLoader {}
 
 #[derive(Debug)]
 pub enum LoadError {
     IoError(std::io::Error),
+    /// A line of an OBJ or MTL file is malformed. `line` is 1-based.
+    ParseError {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
     Other,
 }
 
@@ -21,8 +35,24 @@ impl From<std::io::Error> for LoadError {
     }
 }
 
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::IoError(e) => write!(f, "{}", e),
+            LoadError::ParseError {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+            LoadError::Other => write!(f, "unknown error"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
 impl ObjLoader {
-    pub fn load(filepath: &Path) -> Result<Model, LoadError> {
+    pub fn load(filepath: &Path) -> Result<Mesh, LoadError> {
         let mut file = File::open(filepath)?;
 
         let mut file_string = String::new();
@@ -30,26 +60,40 @@ impl ObjLoader {
 
         let mut temp_vertex_buffer: Vec<Vertex> = Vec::new();
         let mut temp_vertex_normal_buffer: Vec<[f32; 3]> = Vec::new();
-        let mut temp_index_buffer: Vec<[u32; 2]> = Vec::new();
+        // The vertex and normal of each face corner
+        let mut temp_index_buffer: Vec<(u32, Option<u32>)> = Vec::new();
 
         let mut material_library: Option<MaterialLibrary> = None;
         let mut current_material: Option<ObjMaterial> = None;
 
-        for line in file_string.lines() {
+        for (index, line) in file_string.lines().enumerate() {
             let mut elements = line.split_ascii_whitespace();
+            let parse_error = |message: String| LoadError::ParseError {
+                path: filepath.to_path_buf(),
+                line: index + 1,
+                message,
+            };
 
             match elements.next() {
                 Some("v") => ObjLoader::extract_vertex(
                     &mut temp_vertex_buffer,
                     &mut elements,
                     &current_material,
-                ),
+                )
+                .map_err(parse_error)?,
                 Some("vn") => {
                     ObjLoader::extract_vertex_normal(&mut temp_vertex_normal_buffer, &mut elements)
+                        .map_err(parse_error)?
                 }
-                Some("f") => ObjLoader::extract_face(&mut temp_index_buffer, &mut elements),
+                Some("f") => ObjLoader::extract_face(
+                    &mut temp_index_buffer,
+                    &mut elements,
+                    temp_vertex_buffer.len(),
+                    temp_vertex_normal_buffer.len(),
+                )
+                .map_err(parse_error)?,
                 Some("usemtl") => {
-                    let material_name = elements.next().unwrap();
+                    let material_name = next_element(&mut elements).map_err(parse_error)?;
 
                     if let Some(library) = &material_library {
                         if let Some(material) = library.map.get(&String::from(material_name)) {
@@ -58,11 +102,14 @@ impl ObjLoader {
                     }
                 }
                 Some("mtllib") => {
-                    let filename = elements.next().unwrap();
+                    let filename = next_element(&mut elements).map_err(parse_error)?;
 
-                    let full_path = filepath.parent().unwrap().join(filename);
+                    let full_path = match filepath.parent() {
+                        Some(parent) => parent.join(filename),
+                        None => PathBuf::from(filename),
+                    };
 
-                    let library = MaterialLibrary::load(full_path.as_path()).unwrap();
+                    let library = MaterialLibrary::load(full_path.as_path())?;
                     material_library = Some(library);
                 }
                 None => continue,
@@ -73,21 +120,38 @@ impl ObjLoader {
         // Load with normal
         let mut vertex_buffer: Vec<Vertex> = Vec::with_capacity(temp_index_buffer.len() * 3);
 
-        for [vertex_index, vertex_normal_index] in temp_index_buffer {
+        let has_normals = temp_index_buffer
+            .iter()
+            .all(|(_, vertex_normal_index)| vertex_normal_index.is_some());
+
+        for (vertex_index, vertex_normal_index) in temp_index_buffer {
+            let normal = match vertex_normal_index {
+                Some(index) => temp_vertex_normal_buffer[index as usize],
+                None => [0.0, 0.0, 0.0],
+            };
+
             let vertex = Vertex {
                 position: temp_vertex_buffer[vertex_index as usize].position,
                 ambient: temp_vertex_buffer[vertex_index as usize].ambient,
                 diffuse: temp_vertex_buffer[vertex_index as usize].diffuse,
                 specular_exponent: temp_vertex_buffer[vertex_index as usize].specular_exponent,
-                normal: temp_vertex_normal_buffer[vertex_normal_index as usize],
+                normal,
+                tex_coords: [0.0, 0.0],
+                tangent: [0.0, 0.0, 0.0, 0.0],
             };
 
             vertex_buffer.push(vertex);
         }
 
-        let index_buffer: Vec<u32> = (0..vertex_buffer.len() as u32).into_iter().collect();
+        let mut index_buffer: Vec<u32> = (0..vertex_buffer.len() as u32).into_iter().collect();
 
-        let output = Model::from_buffers(vertex_buffer, index_buffer);
+        if !has_normals {
+            ops::compute_smooth_normals(&mut vertex_buffer, &mut index_buffer, CREASE_ANGLE.into());
+        }
+        // The corners of the faces sharing a vertex and a normal become a single vertex
+        ops::weld_vertices(&mut vertex_buffer, &mut index_buffer, 0.0);
+
+        let output = Mesh::from_buffers(vertex_buffer, index_buffer);
 
         Ok(output)
     }
@@ -96,10 +160,8 @@ impl ObjLoader {
         vertex_buffer: &mut Vec<Vertex>,
         elements: &mut std::str::SplitAsciiWhitespace,
         current_material: &Option<ObjMaterial>,
-    ) {
-        let x = f32::from_str(elements.next().unwrap()).unwrap();
-        let y = f32::from_str(elements.next().unwrap()).unwrap();
-        let z = f32::from_str(elements.next().unwrap()).unwrap();
+    ) -> Result<(), String> {
+        let [x, y, z] = parse_vec3(elements)?;
 
         if let Some(material) = current_material {
             let r = material.diffuse[0];
@@ -109,40 +171,70 @@ impl ObjLoader {
         } else {
             vertex_buffer.push(Vertex::new(x, y, z));
         }
+
+        Ok(())
     }
 
     fn extract_vertex_normal(
         vertex_normal_buffer: &mut Vec<[f32; 3]>,
         elements: &mut std::str::SplitAsciiWhitespace,
-    ) {
-        let x = f32::from_str(elements.next().unwrap()).unwrap();
-        let y = f32::from_str(elements.next().unwrap()).unwrap();
-        let z = f32::from_str(elements.next().unwrap()).unwrap();
-        vertex_normal_buffer.push([x, y, z]);
+    ) -> Result<(), String> {
+        vertex_normal_buffer.push(parse_vec3(elements)?);
+        Ok(())
     }
 
+    /// Faces may only reference the `vertex_count` vertices and `normal_count` normals
+    /// declared before them. The normals are optional; when any is missing, they are all
+    /// computed from the faces.
     fn extract_face(
-        index_buffer: &mut Vec<[u32; 2]>,
+        index_buffer: &mut Vec<(u32, Option<u32>)>,
         elements: &mut std::str::SplitAsciiWhitespace,
-    ) {
-        let mut indices = elements.next().unwrap().split("/");
-        let vertex_index1 = u32::from_str(indices.next().unwrap()).unwrap() - 1;
-        indices.next();
-        let vertex_normal_index1 = u32::from_str(indices.next().unwrap()).unwrap() - 1;
-
-        let mut indices = elements.next().unwrap().split("/");
-        let vertex_index2 = u32::from_str(indices.next().unwrap()).unwrap() - 1;
-        indices.next();
-        let vertex_normal_index2 = u32::from_str(indices.next().unwrap()).unwrap() - 1;
-
-        let mut indices = elements.next().unwrap().split("/");
-        let vertex_index3 = u32::from_str(indices.next().unwrap()).unwrap() - 1;
-        indices.next();
-        let vertex_normal_index3 = u32::from_str(indices.next().unwrap()).unwrap() - 1;
-
-        index_buffer.push([vertex_index1, vertex_normal_index1]);
-        index_buffer.push([vertex_index2, vertex_normal_index2]);
-        index_buffer.push([vertex_index3, vertex_normal_index3]);
+        vertex_count: usize,
+        normal_count: usize,
+    ) -> Result<(), String> {
+        for _ in 0..3 {
+            let mut indices = next_element(elements)?.split('/');
+            let vertex_index = parse_index(indices.next(), vertex_count)?;
+            indices.next();
+            let vertex_normal_index = match indices.next() {
+                Some(element) if !element.is_empty() => {
+                    Some(parse_index(Some(element), normal_count)?)
+                }
+                _ => None,
+            };
+
+            index_buffer.push((vertex_index, vertex_normal_index));
+        }
+
+        Ok(())
+    }
+}
+
+fn next_element<'a>(elements: &mut std::str::SplitAsciiWhitespace<'a>) -> Result<&'a str, String> {
+    elements
+        .next()
+        .ok_or_else(|| String::from("missing element"))
+}
+
+fn parse_f32(elements: &mut std::str::SplitAsciiWhitespace) -> Result<f32, String> {
+    let element = next_element(elements)?;
+    f32::from_str(element).map_err(|_| format!("`{}` is not a number", element))
+}
+
+fn parse_vec3(elements: &mut std::str::SplitAsciiWhitespace) -> Result<[f32; 3], String> {
+    Ok([
+        parse_f32(elements)?,
+        parse_f32(elements)?,
+        parse_f32(elements)?,
+    ])
+}
+
+/// Parses a 1-based OBJ index into a 0-based one, lower than `count`.
+fn parse_index(element: Option<&str>, count: usize) -> Result<u32, String> {
+    let element = element.ok_or_else(|| String::from("faces need vertex indices"))?;
+    match u32::from_str(element) {
+        Ok(index) if index > 0 && index as usize <= count => Ok(index - 1),
+        _ => Err(format!("`{}` is not a valid index", element)),
     }
 }
 
@@ -246,8 +338,13 @@ impl MaterialLibrary {
 
         let mut material_library = MaterialLibrary::new();
 
-        for line in file_string.lines() {
+        for (index, line) in file_string.lines().enumerate() {
             let mut elements = line.split_ascii_whitespace();
+            let parse_error = |message: String| LoadError::ParseError {
+                path: path.to_path_buf(),
+                line: index + 1,
+                message,
+            };
 
             match elements.next() {
                 Some("newmtl") => {
@@ -257,25 +354,21 @@ impl MaterialLibrary {
                         material_builder = ObjMaterialBuilder::start();
                     }
 
-                    let name = String::from(elements.next().unwrap());
+                    let name = String::from(next_element(&mut elements).map_err(parse_error)?);
                     material_builder = material_builder.with_name(name);
                 }
                 Some("Kd") => {
-                    let r = f32::from_str(elements.next().unwrap()).unwrap();
-                    let g = f32::from_str(elements.next().unwrap()).unwrap();
-                    let b = f32::from_str(elements.next().unwrap()).unwrap();
+                    let [r, g, b] = parse_vec3(&mut elements).map_err(parse_error)?;
 
                     material_builder = material_builder.with_diffuse_rgb(r, g, b);
                 }
                 Some("Ka") => {
-                    let r = f32::from_str(elements.next().unwrap()).unwrap();
-                    let g = f32::from_str(elements.next().unwrap()).unwrap();
-                    let b = f32::from_str(elements.next().unwrap()).unwrap();
+                    let [r, g, b] = parse_vec3(&mut elements).map_err(parse_error)?;
 
                     material_builder = material_builder.with_ambient_rgb(r, g, b);
                 }
                 Some("Ns") => {
-                    let specular_exponent = f32::from_str(elements.next().unwrap()).unwrap();
+                    let specular_exponent = parse_f32(&mut elements).map_err(parse_error)?;
 
                     material_builder = material_builder.with_specular_exponent(specular_exponent);
                 }
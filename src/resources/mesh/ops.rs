@@ -0,0 +1,530 @@
+//! Processing of the vertices and indices of a mesh, e.g. of a `Mesh` before baking it
+//! or of a loaded file.
+//!
+//! Triangles follow the winding of the whole renderer, see `primitives`: the front
+//! normal of `[a, b, c]` is `(c - a) x (b - a)`.
+
+use std::collections::HashMap;
+
+use cgmath::prelude::*;
+use cgmath::{Rad, Vector3};
+
+use super::super::super::vertex::Vertex;
+
+/// Gives each triangle its own vertices with the normal of the triangle, for a faceted
+/// look.
+pub fn compute_flat_normals(vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>) {
+    let mut flat_vertices = Vec::with_capacity(indices.len());
+    for triangle in indices.chunks_exact(3) {
+        let normal = normalize_or_zero(face_vector(vertices, triangle));
+        for &index in triangle {
+            let mut vertex = vertices[index as usize];
+            vertex.normal = normal.into();
+            flat_vertices.push(vertex);
+        }
+    }
+
+    *indices = (0..flat_vertices.len() as u32).collect();
+    *vertices = flat_vertices;
+}
+
+/// Sets the normal of each vertex to the average of the normals of the triangles
+/// around its position, weighted by their angle at the vertex. Triangles meeting at
+/// more than `crease_angle` don't contribute to each other, so that hard edges stay
+/// sharp: the vertices along them are split. `Rad(PI)` smooths everything.
+///
+/// Vertices at the same position are considered connected even if they are distinct,
+/// e.g. along a texture seam. Vertices used by no triangle are removed.
+pub fn compute_smooth_normals(
+    vertices: &mut Vec<Vertex>,
+    indices: &mut Vec<u32>,
+    crease_angle: Rad<f32>,
+) {
+    let corner_count = indices.len() / 3 * 3;
+    let face_normals: Vec<_> = indices
+        .chunks_exact(3)
+        .map(|triangle| normalize_or_zero(face_vector(vertices, triangle)))
+        .collect();
+
+    let mut corners_at: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+    for corner in 0..corner_count {
+        let position = vertices[indices[corner] as usize].position;
+        corners_at.entry(bits(position)).or_default().push(corner);
+    }
+
+    // Tolerates the rounding errors of coplanar triangles with a crease angle of 0
+    let min_cos = crease_angle.cos() - 1e-6;
+    let mut smooth_vertices = Vec::with_capacity(vertices.len());
+    let mut smooth_indices = Vec::with_capacity(corner_count);
+    // The new vertex of each original vertex and normal
+    let mut split: HashMap<(u32, [u32; 3]), u32> = HashMap::new();
+
+    for corner in 0..corner_count {
+        let face_normal = face_normals[corner / 3];
+        let vertex = vertices[indices[corner] as usize];
+
+        let mut normal = Vector3::zero();
+        for &other in &corners_at[&bits(vertex.position)] {
+            let other_normal = face_normals[other / 3];
+            if other_normal.dot(face_normal) >= min_cos {
+                normal += other_normal * corner_angle(vertices, indices, other);
+            }
+        }
+        let normal = if normal.magnitude2() > 0.0 {
+            normal.normalize()
+        } else {
+            face_normal
+        };
+
+        let key = (indices[corner], bits(normal.into()));
+        let index = *split.entry(key).or_insert_with(|| {
+            let mut vertex = vertex;
+            vertex.normal = normal.into();
+            smooth_vertices.push(vertex);
+            (smooth_vertices.len() - 1) as u32
+        });
+        smooth_indices.push(index);
+    }
+
+    *vertices = smooth_vertices;
+    *indices = smooth_indices;
+}
+
+/// Merges the vertices less than `epsilon` apart whose other attributes also differ by
+/// less than `epsilon`, so that the triangles share them, and removes the vertices used
+/// by no triangle. With an `epsilon` of 0, only exact duplicates are merged.
+pub fn weld_vertices(vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>, epsilon: f32) {
+    let cell = |position: [f32; 3]| -> [i64; 3] {
+        if epsilon > 0.0 {
+            let cell = |x: f32| (x / epsilon).floor() as i64;
+            [cell(position[0]), cell(position[1]), cell(position[2])]
+        } else {
+            let [x, y, z] = bits(position);
+            [x as i64, y as i64, z as i64]
+        }
+    };
+    // Welded vertices may be in the neighboring cells, unless only exact duplicates are
+    let reach = if epsilon > 0.0 { 1 } else { 0 };
+
+    let mut used = vec![false; vertices.len()];
+    for &index in indices.iter() {
+        used[index as usize] = true;
+    }
+
+    let mut welded_vertices: Vec<Vertex> = Vec::new();
+    let mut cells: HashMap<[i64; 3], Vec<u32>> = HashMap::new();
+    let mut remap = vec![0; vertices.len()];
+
+    for (old_index, vertex) in vertices.iter().enumerate() {
+        if !used[old_index] {
+            continue;
+        }
+
+        let [x, y, z] = cell(vertex.position);
+        let mut existing = None;
+        'search: for dx in -reach..=reach {
+            for dy in -reach..=reach {
+                for dz in -reach..=reach {
+                    if let Some(candidates) = cells.get(&[x + dx, y + dy, z + dz]) {
+                        existing = candidates.iter().cloned().find(|&candidate| {
+                            similar(&welded_vertices[candidate as usize], vertex, epsilon)
+                        });
+                        if existing.is_some() {
+                            break 'search;
+                        }
+                    }
+                }
+            }
+        }
+
+        remap[old_index] = existing.unwrap_or_else(|| {
+            welded_vertices.push(*vertex);
+            let index = (welded_vertices.len() - 1) as u32;
+            cells.entry([x, y, z]).or_default().push(index);
+            index
+        });
+    }
+
+    *indices = indices.iter().map(|&index| remap[index as usize]).collect();
+    *vertices = welded_vertices;
+}
+
+/// Removes the triangles with twice the same vertex or an area of at most `epsilon`,
+/// returning how many were removed. Their vertices are kept, see
+/// `remove_unused_vertices`.
+pub fn remove_degenerate_triangles(
+    vertices: &[Vertex],
+    indices: &mut Vec<u32>,
+    epsilon: f32,
+) -> usize {
+    let triangle_count = indices.len() / 3;
+    let kept: Vec<u32> = indices
+        .chunks_exact(3)
+        .filter(|triangle| {
+            let (a, b, c) = (triangle[0], triangle[1], triangle[2]);
+            a != b
+                && b != c
+                && c != a
+                && face_vector(vertices, triangle).magnitude() / 2.0 > epsilon
+        })
+        .flatten()
+        .cloned()
+        .collect();
+
+    let removed = triangle_count - kept.len() / 3;
+    *indices = kept;
+    removed
+}
+
+/// Removes the vertices used by no triangle, keeping the order of the others.
+pub fn remove_unused_vertices(vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>) {
+    let mut remap = vec![None; vertices.len()];
+    for &index in indices.iter() {
+        remap[index as usize] = Some(0);
+    }
+
+    let mut used_vertices = Vec::with_capacity(vertices.len());
+    for (vertex, new_index) in vertices.iter().zip(remap.iter_mut()) {
+        if new_index.is_some() {
+            *new_index = Some(used_vertices.len() as u32);
+            used_vertices.push(*vertex);
+        }
+    }
+
+    *indices = indices
+        .iter()
+        .map(|&index| remap[index as usize].unwrap())
+        .collect();
+    *vertices = used_vertices;
+}
+
+/// Computes the tangent of each vertex from the texture coordinates of its triangles,
+/// orthogonal to its normal, for normal mapping. `w` is the handedness of the
+/// bitangent: `bitangent = w * cross(normal, tangent)`.
+///
+/// The normals must be set first. Vertices whose triangles have no texture mapping get
+/// an arbitrary tangent.
+pub fn compute_tangents(vertices: &mut [Vertex], indices: &[u32]) {
+    let mut tangents = vec![Vector3::zero(); vertices.len()];
+    let mut bitangents = vec![Vector3::zero(); vertices.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let position = |k: usize| Vector3::from(vertices[triangle[k] as usize].position);
+        let tex_coords = |k: usize| vertices[triangle[k] as usize].tex_coords;
+        let (p0, p1, p2) = (position(0), position(1), position(2));
+        let (uv0, uv1, uv2) = (tex_coords(0), tex_coords(1), tex_coords(2));
+
+        let (e1, e2) = (p1 - p0, p2 - p0);
+        let (du1, dv1) = (uv1[0] - uv0[0], uv1[1] - uv0[1]);
+        let (du2, dv2) = (uv2[0] - uv0[0], uv2[1] - uv0[1]);
+
+        let determinant = du1 * dv2 - du2 * dv1;
+        if determinant.abs() < 1e-12 {
+            continue;
+        }
+
+        let tangent = (e1 * dv2 - e2 * dv1) / determinant;
+        let bitangent = (e2 * du1 - e1 * du2) / determinant;
+        for &index in triangle {
+            tangents[index as usize] += tangent;
+            bitangents[index as usize] += bitangent;
+        }
+    }
+
+    for (i, vertex) in vertices.iter_mut().enumerate() {
+        let normal = Vector3::from(vertex.normal);
+        // Gram-Schmidt
+        let tangent = tangents[i] - normal * normal.dot(tangents[i]);
+        let tangent = if tangent.magnitude2() > 1e-12 {
+            tangent.normalize()
+        } else {
+            any_perpendicular(normal)
+        };
+
+        let handedness = if normal.cross(tangent).dot(bitangents[i]) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        vertex.tangent = tangent.extend(handedness).into();
+    }
+}
+
+/// The front normal of `triangle`, twice as long as its area.
+fn face_vector(vertices: &[Vertex], triangle: &[u32]) -> Vector3<f32> {
+    let position = |k: usize| Vector3::from(vertices[triangle[k] as usize].position);
+    let (a, b, c) = (position(0), position(1), position(2));
+    (c - a).cross(b - a)
+}
+
+/// The angle of the triangle at `corner`, an index in `indices`.
+fn corner_angle(vertices: &[Vertex], indices: &[u32], corner: usize) -> f32 {
+    let first = corner / 3 * 3;
+    let position = |k: usize| Vector3::from(vertices[indices[first + k % 3] as usize].position);
+    let k = corner - first;
+    let (p, a, b) = (position(k), position(k + 1), position(k + 2));
+
+    let (e1, e2) = (a - p, b - p);
+    let lengths = e1.magnitude() * e2.magnitude();
+    if lengths == 0.0 {
+        return 0.0;
+    }
+    (e1.dot(e2) / lengths).clamp(-1.0, 1.0).acos()
+}
+
+fn normalize_or_zero(vector: Vector3<f32>) -> Vector3<f32> {
+    if vector.magnitude2() > 0.0 {
+        vector.normalize()
+    } else {
+        vector
+    }
+}
+
+fn any_perpendicular(normal: Vector3<f32>) -> Vector3<f32> {
+    let axis = if normal.x.abs() < 0.9 {
+        Vector3::unit_x()
+    } else {
+        Vector3::unit_y()
+    };
+    let perpendicular = normal.cross(axis);
+    if perpendicular.magnitude2() > 0.0 {
+        perpendicular.normalize()
+    } else {
+        Vector3::unit_x()
+    }
+}
+
+/// Whether `a` and `b` differ by less than `epsilon` in position and in every other
+/// attribute, or are equal.
+fn similar(a: &Vertex, b: &Vertex, epsilon: f32) -> bool {
+    let attributes = |v: &Vertex| {
+        let mut attributes = [0.0; 16];
+        attributes[0..3].copy_from_slice(&v.normal);
+        attributes[3..6].copy_from_slice(&v.ambient);
+        attributes[6..9].copy_from_slice(&v.diffuse);
+        attributes[9] = v.specular_exponent;
+        attributes[10..12].copy_from_slice(&v.tex_coords);
+        attributes[12..16].copy_from_slice(&v.tangent);
+        attributes
+    };
+
+    let distance = (Vector3::from(a.position) - Vector3::from(b.position)).magnitude();
+    (distance < epsilon || a.position == b.position)
+        && attributes(a)
+            .iter()
+            .zip(attributes(b).iter())
+            .all(|(x, y)| (x - y).abs() < epsilon || x == y)
+}
+
+fn bits(vector: [f32; 3]) -> [u32; 3] {
+    // -0.0 and 0.0 are the same position
+    let bits = |x: f32| if x == 0.0 { 0 } else { x.to_bits() };
+    [bits(vector[0]), bits(vector[1]), bits(vector[2])]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cgmath::{Deg, Vector4};
+
+    fn vertex(position: [f32; 3], tex_coords: [f32; 2]) -> Vertex {
+        Vertex {
+            tex_coords,
+            ..Vertex::new(position[0], position[1], position[2])
+        }
+    }
+
+    fn assert_close(actual: Vector3<f32>, expected: Vector3<f32>) {
+        assert!(
+            (actual - expected).magnitude() < 1e-5,
+            "{:?} is not {:?}",
+            actual,
+            expected
+        );
+    }
+
+    /// A unit square in the XZ plane facing up, `u` along X and `v` along Z, as two
+    /// triangles with vertices of their own.
+    fn unwelded_quad() -> (Vec<Vertex>, Vec<u32>) {
+        let (p0, p1, p2, p3) = (
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [1.0, 0.0, 1.0],
+            [0.0, 0.0, 1.0],
+        );
+        let vertices = vec![
+            vertex(p0, [0.0, 0.0]),
+            vertex(p1, [1.0, 0.0]),
+            vertex(p2, [1.0, 1.0]),
+            vertex(p0, [0.0, 0.0]),
+            vertex(p2, [1.0, 1.0]),
+            vertex(p3, [0.0, 1.0]),
+        ];
+        (vertices, (0..6).collect())
+    }
+
+    /// Two triangles sharing the edge from the origin to X, their normals `angle` apart:
+    /// the first one faces up, the second one is folded down around X.
+    fn folded_triangles(angle: Deg<f32>) -> (Vec<Vertex>, Vec<u32>) {
+        let (sin, cos) = Rad::from(angle).0.sin_cos();
+        let vertices = vec![
+            vertex([0.0, 0.0, 0.0], [0.0, 0.0]),
+            vertex([1.0, 0.0, 0.0], [1.0, 0.0]),
+            vertex([0.5, 0.0, 1.0], [0.5, 1.0]),
+            vertex([0.5, -sin, -cos], [0.5, 1.0]),
+        ];
+        (vertices, vec![0, 1, 2, 1, 0, 3])
+    }
+
+    fn normal(vertex: &Vertex) -> Vector3<f32> {
+        Vector3::from(vertex.normal)
+    }
+
+    #[test]
+    fn weld_merges_duplicates() {
+        let (mut vertices, mut indices) = unwelded_quad();
+        weld_vertices(&mut vertices, &mut indices, 0.0);
+
+        assert_eq!(vertices.len(), 4);
+        assert_eq!(indices, vec![0, 1, 2, 0, 2, 3]);
+    }
+
+    #[test]
+    fn weld_merges_within_epsilon() {
+        let (mut vertices, mut indices) = unwelded_quad();
+        vertices[3].position[0] += 1e-4;
+
+        let (mut exact_vertices, mut exact_indices) = (vertices.clone(), indices.clone());
+        weld_vertices(&mut exact_vertices, &mut exact_indices, 0.0);
+        assert_eq!(exact_vertices.len(), 5);
+
+        weld_vertices(&mut vertices, &mut indices, 1e-3);
+        assert_eq!(vertices.len(), 4);
+        assert_eq!(indices.len(), 6);
+    }
+
+    #[test]
+    fn weld_keeps_distinct_attributes() {
+        let (mut vertices, mut indices) = unwelded_quad();
+        vertices[3].tex_coords = [0.5, 0.5];
+
+        weld_vertices(&mut vertices, &mut indices, 1e-3);
+        assert_eq!(vertices.len(), 5);
+    }
+
+    #[test]
+    fn weld_removes_unused_vertices() {
+        let (mut vertices, mut indices) = unwelded_quad();
+        vertices.push(vertex([2.0, 0.0, 0.0], [0.0, 0.0]));
+
+        weld_vertices(&mut vertices, &mut indices, 0.0);
+        assert_eq!(vertices.len(), 4);
+        assert!(indices.iter().all(|&index| index < 4));
+    }
+
+    #[test]
+    fn degenerate_triangles_then_unused_vertices_are_removed() {
+        let (mut vertices, mut indices) = unwelded_quad();
+        vertices.push(vertex([2.0, 0.0, 0.0], [0.0, 0.0]));
+        vertices.push(vertex([3.0, 0.0, 0.0], [0.0, 0.0]));
+        // Twice the same vertex, then three aligned vertices
+        indices.extend_from_slice(&[6, 6, 7, 0, 1, 6]);
+
+        assert_eq!(remove_degenerate_triangles(&vertices, &mut indices, 0.0), 2);
+        assert_eq!(indices.len(), 6);
+        assert_eq!(vertices.len(), 8);
+
+        remove_unused_vertices(&mut vertices, &mut indices);
+        assert_eq!(vertices.len(), 6);
+        assert_eq!(indices, (0..6).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn unused_vertices_are_removed_in_order() {
+        let mut vertices: Vec<Vertex> = (0..5)
+            .map(|i| vertex([i as f32, 0.0, 0.0], [0.0, 0.0]))
+            .collect();
+        let mut indices = vec![4, 2, 0];
+
+        remove_unused_vertices(&mut vertices, &mut indices);
+        assert_eq!(vertices.len(), 3);
+        assert_eq!(indices, vec![2, 1, 0]);
+        let xs: Vec<f32> = vertices.iter().map(|v| v.position[0]).collect();
+        assert_eq!(xs, vec![0.0, 2.0, 4.0]);
+    }
+
+    #[test]
+    fn edges_sharper_than_the_crease_angle_are_split() {
+        let (mut vertices, mut indices) = folded_triangles(Deg(60.0));
+        compute_smooth_normals(&mut vertices, &mut indices, Deg(45.0).into());
+
+        // The two vertices of the shared edge are split, one copy per face
+        assert_eq!(vertices.len(), 6);
+        assert_eq!(indices.len(), 6);
+
+        let up = Vector3::unit_y();
+        let folded = Vector3::new(0.0, 0.5, -(3f32.sqrt()) / 2.0);
+        for &index in &indices[..3] {
+            assert_close(normal(&vertices[index as usize]), up);
+        }
+        for &index in &indices[3..] {
+            assert_close(normal(&vertices[index as usize]), folded);
+        }
+    }
+
+    #[test]
+    fn edges_smoother_than_the_crease_angle_are_shared() {
+        let (mut vertices, mut indices) = folded_triangles(Deg(60.0));
+        compute_smooth_normals(&mut vertices, &mut indices, Deg(90.0).into());
+
+        assert_eq!(vertices.len(), 4);
+        assert_eq!(indices.len(), 6);
+
+        // Halfway between the two faces along the shared edge
+        let halfway = Vector3::new(0.0, 3f32.sqrt() / 2.0, -0.5);
+        for &index in &[indices[0], indices[1]] {
+            assert_close(normal(&vertices[index as usize]), halfway);
+        }
+        assert_close(normal(&vertices[indices[2] as usize]), Vector3::unit_y());
+    }
+
+    #[test]
+    fn tangents_follow_u() {
+        let (mut vertices, indices) = unwelded_quad();
+        for vertex in vertices.iter_mut() {
+            vertex.normal = [0.0, 1.0, 0.0];
+        }
+
+        compute_tangents(&mut vertices, &indices);
+        for vertex in &vertices {
+            assert_close(Vector4::from(vertex.tangent).truncate(), Vector3::unit_x());
+            // `v` goes along Z, opposite to normal x tangent
+            assert_eq!(vertex.tangent[3], -1.0);
+        }
+    }
+
+    #[test]
+    fn tangents_are_orthogonal_to_the_normals() {
+        let (mut vertices, indices) = unwelded_quad();
+        let normals = [
+            [0.3, 1.0, 0.2],
+            [-0.5, 1.0, 0.0],
+            [0.0, 1.0, -0.7],
+            [0.3, 1.0, 0.2],
+            [0.0, 1.0, -0.7],
+            [0.9, 0.4, 0.1],
+        ];
+        for (vertex, &normal) in vertices.iter_mut().zip(normals.iter()) {
+            vertex.normal = Vector3::from(normal).normalize().into();
+        }
+
+        compute_tangents(&mut vertices, &indices);
+        for vertex in &vertices {
+            let tangent = Vector4::from(vertex.tangent).truncate();
+            assert!((tangent.magnitude() - 1.0).abs() < 1e-5);
+            assert!(normal(vertex).dot(tangent).abs() < 1e-5);
+            assert_eq!(vertex.tangent[3].abs(), 1.0);
+        }
+    }
+}
@@ -1,5 +1,8 @@
 pub mod loader;
+pub mod mesh;
 pub mod model;
+pub mod pipelines;
+pub mod primitives;
 pub mod shaders;
 
-pub use model::Model;
+pub use model::{Material, Mesh, MeshInstance};
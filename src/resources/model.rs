@@ -1,272 +1,231 @@
 use std::sync::Arc;
 
 use super::super::color::Color;
-use super::super::resources::shaders;
+use super::super::resources::{primitives, shaders};
 use super::super::transform::Transform;
-use super::super::vertex::{Vertex, VertexBuilder};
-use super::super::Renderer;
+use super::super::upload::UploadBatch;
+use super::super::vertex::{InstanceData, Vertex, VertexBuilder};
+use super::super::{Error, Renderer, Result};
 
-use vulkano::buffer::{BufferAccess, BufferUsage, CpuAccessibleBuffer, CpuBufferPool};
+use vulkano::buffer::{BufferAccess, BufferUsage, CpuAccessibleBuffer, CpuBufferPool, DeviceLocalBuffer, TypedBufferAccess};
 use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState};
 use vulkano::descriptor::descriptor_set::{FixedSizeDescriptorSetsPool, PersistentDescriptorSet, DescriptorSet};
 use vulkano::descriptor::pipeline_layout::PipelineLayoutAbstract;
 use vulkano::descriptor::descriptor_set::DescriptorSetsCollection;
 use vulkano::pipeline::GraphicsPipelineAbstract;
 
-use cgmath::prelude::*;
+/// How often the geometry of a mesh changes, which decides where `Mesh::bake` puts
+/// its buffers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeshUsage {
+    /// Uploaded once to device-local memory, the fastest to draw from.
+    Static,
+    /// Kept in host-visible memory, for meshes rewritten every frame through
+    /// `MeshBuffers::HostVisible`.
+    Dynamic,
+}
+
+/// The buffers created by `Mesh::bake`.
+pub enum MeshBuffers {
+    DeviceLocal {
+        vertices: Arc<DeviceLocalBuffer<[Vertex]>>,
+        indices: Arc<DeviceLocalBuffer<[u32]>>,
+    },
+    HostVisible {
+        vertices: Arc<CpuAccessibleBuffer<[Vertex]>>,
+        indices: Arc<CpuAccessibleBuffer<[u32]>>,
+    },
+}
 
-pub struct Model {
+/// Geometry and its buffers, baked once and shared through an `Arc<Mesh>` by all the
+/// `MeshInstance`s drawing it.
+pub struct Mesh {
     pub vertices: Vec<Vertex>,
     pub indices: Vec<u32>,
-    pub transform: Transform,
-    pub vertex_buffer: Option<Arc<CpuAccessibleBuffer<[Vertex]>>>,
-    pub index_buffer: Option<Arc<CpuAccessibleBuffer<[u32]>>>,
+    pub usage: MeshUsage,
+    pub buffers: Option<MeshBuffers>,
 }
 
-impl Model {
+impl Mesh {
     pub fn new() -> Self {
         Self {
             vertices: Vec::new(),
             indices: Vec::new(),
-            transform: Transform::new(),
-            vertex_buffer: None,
-            index_buffer: None,
+            usage: MeshUsage::Static,
+            buffers: None,
         }
     }
 
+    /// A cube from `-scale` to `scale` on each axis. See `primitives` for other shapes.
     pub fn cube(scale: f32, color: Color<f32>) -> Self {
-        let vertices = vec![
-            // Bottom
-            VertexBuilder::start()
-                .with_position(-scale, -scale, scale)
-                .with_diffuse(color)
-                .with_ambient(color * 0.2)
-                .with_normal(0.0, -1.0, 0.0)
-                .with_specular_exponent(1000.0)
-                .build()
-                .unwrap(),
-            VertexBuilder::start()
-                .with_position(scale, -scale, scale)
-                .with_diffuse(color)
-                .with_ambient(color * 0.2)
-                .with_normal(0.0, -1.0, 0.0)
-                .with_specular_exponent(1000.0)
-                .build()
-                .unwrap(),
-            VertexBuilder::start()
-                .with_position(scale, -scale, -scale)
-                .with_diffuse(color)
-                .with_ambient(color * 0.2)
-                .with_normal(0.0, -1.0, 0.0)
-                .with_specular_exponent(1000.0)
-                .build()
-                .unwrap(),
-            VertexBuilder::start()
-                .with_position(-scale, -scale, -scale)
-                .with_diffuse(color)
-                .with_ambient(color * 0.2)
-                .with_normal(0.0, -1.0, 0.0)
-                .with_specular_exponent(1000.0)
-                .build()
-                .unwrap(),
-            // Top
-            VertexBuilder::start()
-                .with_position(-scale, scale, scale)
-                .with_diffuse(color)
-                .with_ambient(color * 0.2)
-                .with_normal(0.0, 1.0, 0.0)
-                .with_specular_exponent(1000.0)
-                .build()
-                .unwrap(),
-            VertexBuilder::start()
-                .with_position(scale, scale, scale)
-                .with_diffuse(color)
-                .with_ambient(color * 0.2)
-                .with_normal(0.0, 1.0, 0.0)
-                .with_specular_exponent(1000.0)
-                .build()
-                .unwrap(),
-            VertexBuilder::start()
-                .with_position(scale, scale, -scale)
-                .with_diffuse(color)
-                .with_ambient(color * 0.2)
-                .with_normal(0.0, 1.0, 0.0)
-                .with_specular_exponent(1000.0)
-                .build()
-                .unwrap(),
-            VertexBuilder::start()
-                .with_position(-scale, scale, -scale)
-                .with_diffuse(color)
-                .with_ambient(color * 0.2)
-                .with_normal(0.0, 1.0, 0.0)
-                .with_specular_exponent(1000.0)
-                .build()
-                .unwrap(),
-            // Right
-            VertexBuilder::start()
-                .with_position(scale, -scale, scale)
-                .with_diffuse(color)
-                .with_ambient(color * 0.2)
-                .with_normal(1.0, 0.0, 0.0)
-                .with_specular_exponent(1000.0)
-                .build()
-                .unwrap(),
-            VertexBuilder::start()
-                .with_position(scale, scale, scale)
-                .with_diffuse(color)
-                .with_ambient(color * 0.2)
-                .with_normal(1.0, 0.0, 0.0)
-                .with_specular_exponent(1000.0)
-                .build()
-                .unwrap(),
-            VertexBuilder::start()
-                .with_position(scale, scale, -scale)
-                .with_diffuse(color)
-                .with_ambient(color * 0.2)
-                .with_normal(1.0, 0.0, 0.0)
-                .with_specular_exponent(1000.0)
-                .build()
-                .unwrap(),
-            VertexBuilder::start()
-                .with_position(scale, -scale, -scale)
-                .with_diffuse(color)
-                .with_ambient(color * 0.2)
-                .with_normal(1.0, 0.0, 0.0)
-                .with_specular_exponent(1000.0)
-                .build()
-                .unwrap(),
-            // Left
-            VertexBuilder::start()
-                .with_position(-scale, -scale, scale)
-                .with_diffuse(color)
-                .with_ambient(color * 0.2)
-                .with_normal(-1.0, 0.0, 0.0)
-                .with_specular_exponent(1000.0)
-                .build()
-                .unwrap(),
-            VertexBuilder::start()
-                .with_position(-scale, scale, scale)
-                .with_diffuse(color)
-                .with_ambient(color * 0.2)
-                .with_normal(-1.0, 0.0, 0.0)
-                .with_specular_exponent(1000.0)
-                .build()
-                .unwrap(),
-            VertexBuilder::start()
-                .with_position(-scale, scale, -scale)
-                .with_diffuse(color)
-                .with_ambient(color * 0.2)
-                .with_normal(-1.0, 0.0, 0.0)
-                .with_specular_exponent(1000.0)
-                .build()
-                .unwrap(),
-            VertexBuilder::start()
-                .with_position(-scale, -scale, -scale)
-                .with_diffuse(color)
-                .with_ambient(color * 0.2)
-                .with_normal(-1.0, 0.0, 0.0)
-                .with_specular_exponent(1000.0)
-                .build()
-                .unwrap(),
-            // Front
-            VertexBuilder::start()
-                .with_position(-scale, -scale, scale)
-                .with_diffuse(color)
-                .with_ambient(color * 0.2)
-                .with_normal(0.0, 0.0, 1.0)
-                .with_specular_exponent(1000.0)
-                .build()
-                .unwrap(),
-            VertexBuilder::start()
-                .with_position(scale, -scale, scale)
-                .with_diffuse(color)
-                .with_ambient(color * 0.2)
-                .with_normal(0.0, 0.0, 1.0)
-                .with_specular_exponent(1000.0)
-                .build()
-                .unwrap(),
-            VertexBuilder::start()
-                .with_position(scale, scale, scale)
-                .with_diffuse(color)
-                .with_ambient(color * 0.2)
-                .with_normal(0.0, 0.0, 1.0)
-                .with_specular_exponent(1000.0)
-                .build()
-                .unwrap(),
-            VertexBuilder::start()
-                .with_position(-scale, scale, scale)
-                .with_diffuse(color)
-                .with_ambient(color * 0.2)
-                .with_normal(0.0, 0.0, 1.0)
-                .with_specular_exponent(1000.0)
-                .build()
-                .unwrap(),
-            // Back
-            VertexBuilder::start()
-                .with_position(-scale, -scale, -scale)
-                .with_diffuse(color)
-                .with_ambient(color * 0.2)
-                .with_normal(0.0, 0.0, -1.0)
-                .with_specular_exponent(1000.0)
-                .build()
-                .unwrap(),
-            VertexBuilder::start()
-                .with_position(scale, -scale, -scale)
-                .with_diffuse(color)
-                .with_ambient(color * 0.2)
-                .with_normal(0.0, 0.0, -1.0)
-                .with_specular_exponent(1000.0)
-                .build()
-                .unwrap(),
-            VertexBuilder::start()
-                .with_position(scale, scale, -scale)
-                .with_diffuse(color)
-                .with_ambient(color * 0.2)
-                .with_normal(0.0, 0.0, -1.0)
-                .with_specular_exponent(1000.0)
-                .build()
-                .unwrap(),
-            VertexBuilder::start()
-                .with_position(-scale, scale, -scale)
-                .with_diffuse(color)
-                .with_ambient(color * 0.2)
-                .with_normal(0.0, 0.0, -1.0)
-                .with_specular_exponent(1000.0)
-                .build()
-                .unwrap(),
-        ];
-
-        let indices = vec![
-            3, 0, 1, 3, 1, 2, 4, 7, 6, 4, 6, 5, 8, 9, 10, 8, 10, 11, 12, 15, 14, 12, 14, 13, 16,
-            19, 18, 16, 18, 17, 20, 21, 22, 20, 22, 23,
-        ];
+        primitives::cube(scale * 2.0).with_color(color)
+    }
+
+    /// Paints all the vertices `color`, with a fifth of it as ambient color.
+    pub fn with_color(mut self, color: Color<f32>) -> Self {
+        let (diffuse, ambient) = (color, color * 0.2);
+        for vertex in self.vertices.iter_mut() {
+            vertex.diffuse = [diffuse.r, diffuse.g, diffuse.b];
+            vertex.ambient = [ambient.r, ambient.g, ambient.b];
+        }
+        self
+    }
+
+    pub fn with_usage(mut self, usage: MeshUsage) -> Self {
+        self.usage = usage;
+        self
+    }
+
+    /// Creates the buffers of the mesh, according to its `usage`. Use `bake_all` or
+    /// `bake_with` to upload many static meshes at once.
+    pub fn bake(&mut self, renderer: &Renderer) -> Result<()> {
+        let mut batch = UploadBatch::new(renderer)?;
+        self.bake_with(renderer, &mut batch)?;
+        batch.submit()
+    }
+
+    /// Bakes all the `meshes`, with a single transfer submission.
+    pub fn bake_all<'a, I>(renderer: &Renderer, meshes: I) -> Result<()>
+    where
+        I: IntoIterator<Item = &'a mut Mesh>,
+    {
+        let mut batch = UploadBatch::new(renderer)?;
+        for mesh in meshes {
+            mesh.bake_with(renderer, &mut batch)?;
+        }
+        batch.submit()
+    }
+
+    /// Like `bake`, recording the uploads of a static mesh in `batch`. Its buffers
+    /// can't be drawn from before the batch is submitted.
+    pub fn bake_with(&mut self, renderer: &Renderer, batch: &mut UploadBatch) -> Result<()> {
+        let buffers = match self.usage {
+            MeshUsage::Static => MeshBuffers::DeviceLocal {
+                vertices: batch.upload(
+                    BufferUsage::vertex_buffer(),
+                    self.vertices.iter().cloned(),
+                )?,
+                indices: batch.upload(BufferUsage::index_buffer(), self.indices.iter().cloned())?,
+            },
+            MeshUsage::Dynamic => MeshBuffers::HostVisible {
+                vertices: CpuAccessibleBuffer::from_iter(
+                    renderer.device.clone(),
+                    BufferUsage::vertex_buffer(),
+                    self.vertices.iter().cloned(),
+                )?,
+                indices: CpuAccessibleBuffer::from_iter(
+                    renderer.device.clone(),
+                    BufferUsage::index_buffer(),
+                    self.indices.iter().cloned(),
+                )?,
+            },
+        };
+
+        self.buffers = Some(buffers);
+        Ok(())
+    }
+
+    /// Records the drawing of the mesh with the descriptor `sets`, which hold the data
+    /// of a single instance.
+    pub fn render_with_sets<Gp, D>(
+        &self,
+        command_buffer_builder: AutoCommandBufferBuilder,
+        pipeline: Gp,
+        dynamic_state: &DynamicState,
+        sets: D,
+    ) -> Result<AutoCommandBufferBuilder>
+    where
+        Gp: GraphicsPipelineAbstract + Send + Sync + 'static + Clone,
+        D: DescriptorSetsCollection,
+    {
+        Ok(match &self.buffers {
+            Some(MeshBuffers::DeviceLocal { vertices, indices }) => command_buffer_builder
+                .draw_indexed(
+                    pipeline,
+                    dynamic_state,
+                    vec![vertices.clone()],
+                    indices.clone(),
+                    sets,
+                    (),
+                )?,
+            Some(MeshBuffers::HostVisible { vertices, indices }) => command_buffer_builder
+                .draw_indexed(
+                    pipeline,
+                    dynamic_state,
+                    vec![vertices.clone()],
+                    indices.clone(),
+                    sets,
+                    (),
+                )?,
+            None => return Err(Error::NotBaked),
+        })
+    }
+}
 
+/// How a `MeshInstance` is shaded. Its color multiplies the ambient and diffuse colors
+/// of the vertices, so that a white mesh can be drawn in any color.
+#[derive(Clone, Copy)]
+pub struct Material {
+    pub color: Color<f32>,
+}
+
+impl Material {
+    pub fn new(color: Color<f32>) -> Self {
+        Self { color }
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self::new(Color::<f32>::WHITE)
+    }
+}
+
+/// An object of the scene: a shared mesh, where it is and how it looks.
+#[derive(Clone)]
+pub struct MeshInstance {
+    pub mesh: Arc<Mesh>,
+    pub transform: Transform,
+    pub material: Material,
+}
+
+impl MeshInstance {
+    pub fn new(mesh: Arc<Mesh>) -> Self {
         Self {
-            vertices,
-            indices,
+            mesh,
             transform: Transform::new(),
-            vertex_buffer: None,
-            index_buffer: None,
+            material: Material::default(),
         }
     }
 
-    pub fn bake(&mut self, renderer: &Renderer) {
-        let vertex_buffer = CpuAccessibleBuffer::from_iter(
-            renderer.device.clone(),
-            BufferUsage::vertex_buffer(),
-            self.vertices.clone().into_iter(),
-        )
-        .unwrap();
+    pub fn with_material(mut self, material: Material) -> Self {
+        self.material = material;
+        self
+    }
 
-        let index_buffer = CpuAccessibleBuffer::from_iter(
-            renderer.device.clone(),
-            BufferUsage::index_buffer(),
-            self.indices.clone().into_iter(),
-        )
-        .unwrap();
+    /// The uniform data of the instance, see `model_data`.
+    pub fn model_data(&self) -> shaders::basic::vertex::ty::ModelData {
+        model_data(&self.transform, &self.material)
+    }
+
+    /// The attributes of the instance when drawn with `InstancedRenderable`.
+    pub fn instance_data(&self) -> InstanceData {
+        InstanceData::new(&self.transform).with_color(self.material.color)
+    }
+}
+
+/// The uniform data of an instance drawn with `transform` and `material`, e.g. an
+/// interpolated transform.
+pub fn model_data(
+    transform: &Transform,
+    material: &Material,
+) -> shaders::basic::vertex::ty::ModelData {
+    let instance = InstanceData::new(transform).with_color(material.color);
 
-        self.vertex_buffer = Some(vertex_buffer);
-        self.index_buffer = Some(index_buffer);
+    shaders::basic::vertex::ty::ModelData {
+        model_matrix: instance.model_matrix,
+        normal_matrix: instance.normal_matrix,
+        color: instance.color,
     }
 }
 
@@ -274,26 +233,24 @@ pub trait FromBuffers<V, I> {
     fn from_buffers(v: V, i: I) -> Self;
 }
 
-impl FromBuffers<Vec<Vertex>, Vec<u32>> for Model {
-    fn from_buffers(vertices: Vec<Vertex>, indices: Vec<u32>) -> Model {
-        Model {
+impl FromBuffers<Vec<Vertex>, Vec<u32>> for Mesh {
+    fn from_buffers(vertices: Vec<Vertex>, indices: Vec<u32>) -> Mesh {
+        Mesh {
             vertices,
             indices,
-            transform: Transform::new(),
-            vertex_buffer: None,
-            index_buffer: None,
+            usage: MeshUsage::Static,
+            buffers: None,
         }
     }
 }
 
-impl FromBuffers<&Vec<Vertex>, &Vec<u32>> for Model {
-    fn from_buffers(vertices: &Vec<Vertex>, indices: &Vec<u32>) -> Model {
-        Model {
+impl FromBuffers<&Vec<Vertex>, &Vec<u32>> for Mesh {
+    fn from_buffers(vertices: &Vec<Vertex>, indices: &Vec<u32>) -> Mesh {
+        Mesh {
             vertices: vertices.clone(),
             indices: indices.clone(),
-            transform: Transform::new(),
-            vertex_buffer: None,
-            index_buffer: None,
+            usage: MeshUsage::Static,
+            buffers: None,
         }
     }
 }
@@ -346,10 +303,11 @@ pub trait Renderable {
         &self,
         command_buffer_builder: AutoCommandBufferBuilder,
         pipeline: Gp,
+        dynamic_state: &DynamicState,
         world_descriptor_set: Arc<D>,
         model_data_uniform_buffer: &CpuBufferPool<shaders::basic::vertex::ty::ModelData>,
         pool: &mut FixedSizeDescriptorSetsPool<L>,
-    ) -> AutoCommandBufferBuilder
+    ) -> Result<AutoCommandBufferBuilder>
     where
         Gp: GraphicsPipelineAbstract + Send + Sync + 'static + Clone,
         D: DescriptorSet + Send + Sync + 'static,
@@ -359,73 +317,109 @@ pub trait Renderable {
         &self,
         command_buffer_builder: AutoCommandBufferBuilder,
         pipeline: Gp,
+        dynamic_state: &DynamicState,
         sets: D
-    ) -> AutoCommandBufferBuilder
+    ) -> Result<AutoCommandBufferBuilder>
     where
         Gp: GraphicsPipelineAbstract + Send + Sync + 'static + Clone,
         D: DescriptorSetsCollection;
 }
 
-impl Renderable for Model {
+impl Renderable for MeshInstance {
     fn render<Gp, D, L>(
         &self,
         command_buffer_builder: AutoCommandBufferBuilder,
         pipeline: Gp,
+        dynamic_state: &DynamicState,
         world_descriptor_set: Arc<D>,
         model_data_uniform_buffer: &CpuBufferPool<shaders::basic::vertex::ty::ModelData>,
         pool: &mut FixedSizeDescriptorSetsPool<L>,
-    ) -> AutoCommandBufferBuilder
+    ) -> Result<AutoCommandBufferBuilder>
     where
         Gp: GraphicsPipelineAbstract + Send + Sync + 'static + Clone,
         D: DescriptorSet + Send + Sync + 'static,
         L: PipelineLayoutAbstract + Send + Sync + Clone + 'static,
     {
-        assert_eq!(self.vertex_buffer.is_some(), true);
-        assert_eq!(self.index_buffer.is_some(), true);
-
-        let model_data_subbuffer = {
-            let normal_matrix = self.transform.model_matrix().invert().unwrap().transpose();
-
-            let uniform_data = shaders::basic::vertex::ty::ModelData {
-                model_matrix: self.transform.model_matrix().into(),
-                normal_matrix: normal_matrix.into(),
-            };
-
-            model_data_uniform_buffer.next(uniform_data).unwrap()
-        };
+        let model_data_subbuffer = model_data_uniform_buffer.next(self.model_data())?;
 
-        let set = Arc::new(
-            pool.next()
-                .add_buffer(model_data_subbuffer)
-                .unwrap()
-                .build()
-                .unwrap(),
-        );
+        let set = Arc::new(pool.next().add_buffer(model_data_subbuffer)?.build()?);
 
-        self.render_with_sets(command_buffer_builder, pipeline, (world_descriptor_set.clone(), set.clone()))
+        self.render_with_sets(
+            command_buffer_builder,
+            pipeline,
+            dynamic_state,
+            (world_descriptor_set.clone(), set.clone()),
+        )
     }
 
     fn render_with_sets<Gp, D>(
         &self,
         command_buffer_builder: AutoCommandBufferBuilder,
         pipeline: Gp,
+        dynamic_state: &DynamicState,
         sets: D
-    ) -> AutoCommandBufferBuilder
+    ) -> Result<AutoCommandBufferBuilder>
     where
         Gp: GraphicsPipelineAbstract + Send + Sync + 'static + Clone,
         D: DescriptorSetsCollection,
     {
-        assert_eq!(self.vertex_buffer.is_some(), true);
-        assert_eq!(self.index_buffer.is_some(), true);
-        command_buffer_builder
-            .draw_indexed(
-                pipeline,
-                &DynamicState::none(),
-                vec![self.vertex_buffer.as_ref().unwrap().clone()],
-                self.index_buffer.as_ref().unwrap().clone(),
-                sets,
-                (),
-            )
-            .unwrap()
+        self.mesh
+            .render_with_sets(command_buffer_builder, pipeline, dynamic_state, sets)
+    }
+}
+
+/// Draws any number of instances of a mesh with a single call, using a pipeline from
+/// `create_instanced_pipeline`. `instances` holds the transform and color of each one,
+/// and `sets` the world data.
+pub trait InstancedRenderable {
+    fn render_instanced<Gp, D, B>(
+        &self,
+        command_buffer_builder: AutoCommandBufferBuilder,
+        pipeline: Gp,
+        dynamic_state: &DynamicState,
+        instances: B,
+        sets: D,
+    ) -> Result<AutoCommandBufferBuilder>
+    where
+        Gp: GraphicsPipelineAbstract + Send + Sync + 'static + Clone,
+        D: DescriptorSetsCollection,
+        B: TypedBufferAccess<Content = [InstanceData]> + Send + Sync + 'static;
+}
+
+impl InstancedRenderable for Mesh {
+    fn render_instanced<Gp, D, B>(
+        &self,
+        command_buffer_builder: AutoCommandBufferBuilder,
+        pipeline: Gp,
+        dynamic_state: &DynamicState,
+        instances: B,
+        sets: D,
+    ) -> Result<AutoCommandBufferBuilder>
+    where
+        Gp: GraphicsPipelineAbstract + Send + Sync + 'static + Clone,
+        D: DescriptorSetsCollection,
+        B: TypedBufferAccess<Content = [InstanceData]> + Send + Sync + 'static,
+    {
+        if instances.len() == 0 {
+            return Ok(command_buffer_builder);
+        }
+
+        let instances: Arc<dyn BufferAccess + Send + Sync> = Arc::new(instances);
+        let (vertices, indices): (Arc<dyn BufferAccess + Send + Sync>, _) = match &self.buffers {
+            Some(MeshBuffers::DeviceLocal { vertices, indices }) => {
+                (vertices.clone(), indices.clone() as Arc<dyn TypedBufferAccess<Content = [u32]> + Send + Sync>)
+            }
+            Some(MeshBuffers::HostVisible { vertices, indices }) => (vertices.clone(), indices.clone() as _),
+            None => return Err(Error::NotBaked),
+        };
+
+        Ok(command_buffer_builder.draw_indexed(
+            pipeline,
+            dynamic_state,
+            vec![vertices, instances],
+            indices,
+            sets,
+            (),
+        )?)
     }
 }
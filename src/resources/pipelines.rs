@@ -0,0 +1,138 @@
+use std::sync::Arc;
+
+use vulkano::device::Device;
+use vulkano::format::Format;
+use vulkano::framebuffer::{RenderPassAbstract, Subpass};
+use vulkano::pipeline::vertex::OneVertexOneInstanceDefinition;
+use vulkano::pipeline::{GraphicsPipeline, GraphicsPipelineAbstract};
+
+use super::super::vertex::{InstanceData, Vertex};
+use super::super::{Error, Result};
+use super::shaders;
+
+/// A render pass with a single subpass drawing into a color and a depth attachment.
+/// With more than one sample, both are multisampled and the color is resolved into a
+/// third, single-sampled attachment of the same format.
+pub fn create_render_pass(
+    device: Arc<Device>,
+    color_format: Format,
+    depth_format: Format,
+    samples: u32,
+) -> Result<Arc<dyn RenderPassAbstract + Send + Sync>> {
+    if samples > 1 {
+        return Ok(Arc::new(vulkano::single_pass_renderpass!(device,
+            attachments: {
+                color: {
+                    load: Clear,
+                    store: DontCare,
+                    format: color_format,
+                    samples: samples,
+                },
+                depth: {
+                    load: Clear,
+                    store: DontCare,
+                    format: depth_format,
+                    samples: samples,
+                },
+                resolve: {
+                    load: DontCare,
+                    store: Store,
+                    format: color_format,
+                    samples: 1,
+                }
+            },
+            pass: {
+                color: [color],
+                depth_stencil: {depth},
+                resolve: [resolve]
+            }
+        )?));
+    }
+
+    Ok(Arc::new(vulkano::single_pass_renderpass!(device,
+        attachments: {
+            color: {
+                load: Clear,
+                store: Store,
+                format: color_format,
+                samples: 1,
+            },
+            depth: {
+                load: Clear,
+                store: DontCare,
+                format: depth_format,
+                samples: 1,
+            }
+        },
+        pass: {
+            color: [color],
+            depth_stencil: {depth}
+        }
+    )?))
+}
+
+/// The pipeline lighting models with the basic shaders, drawing into the first subpass
+/// of `render_pass`. The viewport is dynamic, see `ApplicationState::dynamic_state`.
+pub fn create_basic_pipeline(
+    device: Arc<Device>,
+    vs: &shaders::basic::vertex::Shader,
+    fs: &shaders::basic::fragment::Shader,
+    render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+) -> Result<Arc<dyn GraphicsPipelineAbstract + Send + Sync>> {
+    Ok(Arc::new(
+        GraphicsPipeline::start()
+            .vertex_input_single_buffer::<Vertex>()
+            .vertex_shader(vs.main_entry_point(), ())
+            .triangle_list()
+            .viewports_dynamic_scissors_irrelevant(1)
+            .fragment_shader(fs.main_entry_point(), ())
+            .depth_stencil_simple_depth()
+            .render_pass(Subpass::from(render_pass, 0).ok_or(Error::MissingSubpass(0))?)
+            .cull_mode_back()
+            .build(device)?,
+    ))
+}
+
+/// Like `create_basic_pipeline`, reading the transform and color of each instance from
+/// a second vertex buffer of `InstanceData`, see `InstancedRenderable`.
+pub fn create_instanced_pipeline(
+    device: Arc<Device>,
+    vs: &shaders::instanced::vertex::Shader,
+    fs: &shaders::basic::fragment::Shader,
+    render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+) -> Result<Arc<dyn GraphicsPipelineAbstract + Send + Sync>> {
+    Ok(Arc::new(
+        GraphicsPipeline::start()
+            .vertex_input(OneVertexOneInstanceDefinition::<Vertex, InstanceData>::new())
+            .vertex_shader(vs.main_entry_point(), ())
+            .triangle_list()
+            .viewports_dynamic_scissors_irrelevant(1)
+            .fragment_shader(fs.main_entry_point(), ())
+            .depth_stencil_simple_depth()
+            .render_pass(Subpass::from(render_pass, 0).ok_or(Error::MissingSubpass(0))?)
+            .cull_mode_back()
+            .build(device)?,
+    ))
+}
+
+/// The pipeline drawing gizmos as lines of `line_width`.
+pub fn create_gizmo_pipeline(
+    device: Arc<Device>,
+    vs: &shaders::gizmo::vertex::Shader,
+    fs: &shaders::gizmo::fragment::Shader,
+    render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    line_width: f32,
+) -> Result<Arc<dyn GraphicsPipelineAbstract + Send + Sync>> {
+    Ok(Arc::new(
+        GraphicsPipeline::start()
+            .line_list()
+            .line_width(line_width)
+            .vertex_input_single_buffer::<Vertex>()
+            .vertex_shader(vs.main_entry_point(), ())
+            .fragment_shader(fs.main_entry_point(), ())
+            .viewports_dynamic_scissors_irrelevant(1)
+            .depth_stencil_simple_depth()
+            .render_pass(Subpass::from(render_pass, 0).ok_or(Error::MissingSubpass(0))?)
+            .build(device)?,
+    ))
+}
@@ -0,0 +1,625 @@
+//! Procedural meshes for markers, axes and placeholder geometry.
+//!
+//! The meshes are white, to be colored by a `Material` or `Mesh::with_color`, and
+//! centered on the origin with their axis along Y unless stated otherwise. Their
+//! triangles are front-facing for `cull_mode_back`, and their texture coordinates go
+//! from 0 to 1, `v` increasing downwards, except along the seam of `icosphere` where
+//! `u` slightly exceeds 1 so that repeating textures wrap.
+
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use cgmath::prelude::*;
+use cgmath::Vector3;
+
+use super::super::vertex::Vertex;
+use super::mesh::ops::compute_tangents;
+use super::model::{FromBuffers, Mesh};
+
+/// A box of `size` on each side.
+pub fn cube(size: f32) -> Mesh {
+    cuboid(size, size, size)
+}
+
+/// A box of `width` along X, `height` along Y and `depth` along Z. Each face is mapped
+/// to the whole texture.
+pub fn cuboid(width: f32, height: f32, depth: f32) -> Mesh {
+    let x = Vector3::unit_x() * width / 2.0;
+    let y = Vector3::unit_y() * height / 2.0;
+    let z = Vector3::unit_z() * depth / 2.0;
+
+    let mut builder = MeshBuilder::new();
+    // Each face as its center, then `u` and `v` axes such that `u` x `v` is its normal
+    for &(center, u, v) in &[
+        (x, -z, y),
+        (-x, z, y),
+        (y, x, -z),
+        (-y, x, z),
+        (z, x, y),
+        (-z, -x, y),
+    ] {
+        let normal = center.normalize();
+        let a = builder.vertex(center - u - v, normal, [0.0, 1.0]);
+        let b = builder.vertex(center + u - v, normal, [1.0, 1.0]);
+        let c = builder.vertex(center + u + v, normal, [1.0, 0.0]);
+        let d = builder.vertex(center - u + v, normal, [0.0, 0.0]);
+        builder.quad(a, b, c, d);
+    }
+
+    builder.build()
+}
+
+/// A single quad of `width` along X and `depth` along Z, facing up.
+pub fn plane(width: f32, depth: f32) -> Mesh {
+    grid(width, depth, 1, 1)
+}
+
+/// A plane of `width` along X and `depth` along Z facing up, divided in `columns` by
+/// `rows` quads, e.g. to be displaced.
+pub fn grid(width: f32, depth: f32, columns: u32, rows: u32) -> Mesh {
+    let (columns, rows) = (columns.max(1), rows.max(1));
+
+    let mut builder = MeshBuilder::new();
+    for j in 0..=rows {
+        for i in 0..=columns {
+            let (u, v) = (i as f32 / columns as f32, j as f32 / rows as f32);
+            let position = Vector3::new((u - 0.5) * width, 0.0, (v - 0.5) * depth);
+            builder.vertex(position, Vector3::unit_y(), [u, v]);
+        }
+    }
+
+    let stride = columns + 1;
+    for j in 0..rows {
+        for i in 0..columns {
+            let a = j * stride + i;
+            builder.quad(a, a + stride, a + stride + 1, a + 1);
+        }
+    }
+
+    builder.build()
+}
+
+/// A sphere made of `sectors` slices around Y and `stacks` from pole to pole.
+pub fn uv_sphere(radius: f32, sectors: u32, stacks: u32) -> Mesh {
+    let stacks = stacks.max(2);
+    let profile: Vec<_> = (0..=stacks)
+        .map(|j| {
+            let (sin, cos) = (PI * j as f32 / stacks as f32).sin_cos();
+            ProfilePoint::new(radius * sin, radius * cos, sin, cos)
+        })
+        .collect();
+
+    let mut builder = MeshBuilder::new();
+    builder.lathe(&[&profile], sectors);
+    builder.build()
+}
+
+/// A sphere made of evenly sized triangles, subdividing each face of an icosahedron
+/// `subdivisions` times. 20 triangles at 0, 4 times more at each level.
+pub fn icosphere(radius: f32, subdivisions: u32) -> Mesh {
+    let t = (1.0 + 5f32.sqrt()) / 2.0;
+    let mut positions: Vec<Vector3<f32>> = [
+        [-1.0, t, 0.0],
+        [1.0, t, 0.0],
+        [-1.0, -t, 0.0],
+        [1.0, -t, 0.0],
+        [0.0, -1.0, t],
+        [0.0, 1.0, t],
+        [0.0, -1.0, -t],
+        [0.0, 1.0, -t],
+        [t, 0.0, -1.0],
+        [t, 0.0, 1.0],
+        [-t, 0.0, -1.0],
+        [-t, 0.0, 1.0],
+    ]
+    .iter()
+    .map(|&p| Vector3::from(p).normalize())
+    .collect();
+
+    // Counter-clockwise seen from the outside
+    let mut triangles: Vec<[u32; 3]> = vec![
+        [0, 11, 5],
+        [0, 5, 1],
+        [0, 1, 7],
+        [0, 7, 10],
+        [0, 10, 11],
+        [1, 5, 9],
+        [5, 11, 4],
+        [11, 10, 2],
+        [10, 7, 6],
+        [7, 1, 8],
+        [3, 9, 4],
+        [3, 4, 2],
+        [3, 2, 6],
+        [3, 6, 8],
+        [3, 8, 9],
+        [4, 9, 5],
+        [2, 4, 11],
+        [6, 2, 10],
+        [8, 6, 7],
+        [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut midpoint = |a: u32, b: u32| {
+            let key = (a.min(b), a.max(b));
+            *midpoints.entry(key).or_insert_with(|| {
+                let position = (positions[a as usize] + positions[b as usize]).normalize();
+                positions.push(position);
+                (positions.len() - 1) as u32
+            })
+        };
+
+        triangles = triangles
+            .iter()
+            .flat_map(|&[a, b, c]| {
+                let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                vec![[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    let mut builder = MeshBuilder::new();
+    for &position in &positions {
+        builder.vertex(position * radius, position, sphere_tex_coords(position));
+    }
+
+    // The vertices of the triangles crossing the seam at u = 0 get a copy at u > 1,
+    // and the poles one per triangle, at the u of the triangle
+    let is_pole = |vertex: &Vertex| vertex.normal[0].abs() < 1e-6 && vertex.normal[2].abs() < 1e-6;
+    let mut seam_copies: HashMap<u32, u32> = HashMap::new();
+    for [a, b, c] in triangles {
+        let mut corners = [a, b, c];
+        let us: Vec<f32> = corners
+            .iter()
+            .map(|&i| &builder.vertices[i as usize])
+            .filter(|vertex| !is_pole(vertex))
+            .map(|vertex| vertex.tex_coords[0])
+            .collect();
+        let crosses_seam =
+            us.iter().cloned().fold(0.0, f32::max) - us.iter().cloned().fold(1.0, f32::min) > 0.5;
+
+        if crosses_seam {
+            for corner in corners.iter_mut() {
+                let vertex = &builder.vertices[*corner as usize];
+                if !is_pole(vertex) && vertex.tex_coords[0] < 0.5 {
+                    let original = *corner;
+                    *corner = *seam_copies.entry(original).or_insert_with(|| {
+                        let mut vertex = builder.vertices[original as usize];
+                        vertex.tex_coords[0] += 1.0;
+                        builder.vertices.push(vertex);
+                        (builder.vertices.len() - 1) as u32
+                    });
+                }
+            }
+        }
+
+        for k in 0..3 {
+            let vertex = builder.vertices[corners[k] as usize];
+            if is_pole(&vertex) {
+                let others = [corners[(k + 1) % 3], corners[(k + 2) % 3]];
+                let u = others
+                    .iter()
+                    .map(|&i| builder.vertices[i as usize].tex_coords[0])
+                    .sum::<f32>()
+                    / 2.0;
+
+                let mut pole = vertex;
+                pole.tex_coords[0] = u;
+                builder.vertices.push(pole);
+                corners[k] = (builder.vertices.len() - 1) as u32;
+            }
+        }
+
+        builder.triangle(corners[0], corners[1], corners[2]);
+    }
+
+    builder.build()
+}
+
+/// A closed cylinder made of `sectors` slices around Y.
+pub fn cylinder(radius: f32, height: f32, sectors: u32) -> Mesh {
+    let h = height / 2.0;
+
+    let mut builder = MeshBuilder::new();
+    builder.lathe(
+        &[
+            &[
+                ProfilePoint::new(0.0, h, 0.0, 1.0),
+                ProfilePoint::new(radius, h, 0.0, 1.0),
+            ],
+            &[
+                ProfilePoint::new(radius, h, 1.0, 0.0),
+                ProfilePoint::new(radius, -h, 1.0, 0.0),
+            ],
+            &[
+                ProfilePoint::new(radius, -h, 0.0, -1.0),
+                ProfilePoint::new(0.0, -h, 0.0, -1.0),
+            ],
+        ],
+        sectors,
+    );
+    builder.build()
+}
+
+/// A closed cone made of `sectors` slices around Y, pointing up.
+pub fn cone(radius: f32, height: f32, sectors: u32) -> Mesh {
+    let h = height / 2.0;
+
+    let mut builder = MeshBuilder::new();
+    builder.lathe(
+        &[
+            &[
+                ProfilePoint::new(0.0, h, height, radius),
+                ProfilePoint::new(radius, -h, height, radius),
+            ],
+            &[
+                ProfilePoint::new(radius, -h, 0.0, -1.0),
+                ProfilePoint::new(0.0, -h, 0.0, -1.0),
+            ],
+        ],
+        sectors,
+    );
+    builder.build()
+}
+
+/// A ring in the XZ plane, `major_radius` from its center to the center of the tube,
+/// made of `major_segments` slices of `minor_segments` quads around the tube.
+pub fn torus(
+    major_radius: f32,
+    minor_radius: f32,
+    major_segments: u32,
+    minor_segments: u32,
+) -> Mesh {
+    let minor_segments = minor_segments.max(3);
+    // Around the tube from its top, going outwards first
+    let profile: Vec<_> = (0..=minor_segments)
+        .map(|j| {
+            let angle = PI / 2.0 - 2.0 * PI * j as f32 / minor_segments as f32;
+            let (sin, cos) = angle.sin_cos();
+            ProfilePoint::new(
+                major_radius + minor_radius * cos,
+                minor_radius * sin,
+                cos,
+                sin,
+            )
+        })
+        .collect();
+
+    let mut builder = MeshBuilder::new();
+    builder.lathe(&[&profile], major_segments);
+    builder.build()
+}
+
+/// A cylinder of `length` capped by two hemispheres, each made of `stacks` rings, for
+/// a total height of `length + 2 * radius`.
+pub fn capsule(radius: f32, length: f32, sectors: u32, stacks: u32) -> Mesh {
+    let (h, stacks) = (length / 2.0, stacks.max(1));
+    let hemisphere_point = |j: u32, center: f32| {
+        let (sin, cos) = (PI / 2.0 * j as f32 / stacks as f32).sin_cos();
+        ProfilePoint::new(radius * sin, center + radius * cos, sin, cos)
+    };
+
+    // The quad between the two equators is the cylinder
+    let profile: Vec<_> = (0..=stacks)
+        .map(|j| hemisphere_point(j, h))
+        .chain((stacks..=2 * stacks).map(|j| hemisphere_point(j, -h)))
+        .collect();
+
+    let mut builder = MeshBuilder::new();
+    builder.lathe(&[&profile], sectors);
+    builder.build()
+}
+
+/// An arrow from the origin to `length` along Y: a shaft of `radius` and a head twice
+/// as wide, a quarter of the length long.
+pub fn arrow(length: f32, radius: f32, sectors: u32) -> Mesh {
+    let (head_radius, head_length) = (radius * 2.0, length / 4.0);
+    let shaft_length = length - head_length;
+
+    let mut builder = MeshBuilder::new();
+    builder.lathe(
+        &[
+            &[
+                ProfilePoint::new(0.0, length, head_length, head_radius),
+                ProfilePoint::new(head_radius, shaft_length, head_length, head_radius),
+            ],
+            &[
+                ProfilePoint::new(head_radius, shaft_length, 0.0, -1.0),
+                ProfilePoint::new(radius, shaft_length, 0.0, -1.0),
+            ],
+            &[
+                ProfilePoint::new(radius, shaft_length, 1.0, 0.0),
+                ProfilePoint::new(radius, 0.0, 1.0, 0.0),
+            ],
+            &[
+                ProfilePoint::new(radius, 0.0, 0.0, -1.0),
+                ProfilePoint::new(0.0, 0.0, 0.0, -1.0),
+            ],
+        ],
+        sectors,
+    );
+    builder.build()
+}
+
+/// The coordinates of a point of the unit sphere in the mapping of `uv_sphere`.
+fn sphere_tex_coords(position: Vector3<f32>) -> [f32; 2] {
+    let u = (-position.z).atan2(position.x) / (2.0 * PI);
+    let v = position.y.clamp(-1.0, 1.0).acos() / PI;
+    [if u < 0.0 { u + 1.0 } else { u }, v]
+}
+
+/// A point of a profile turned around the Y axis by `MeshBuilder::lathe`: its distance
+/// to the axis, its height, and the normal of the surface in that plane.
+#[derive(Clone, Copy)]
+struct ProfilePoint {
+    radius: f32,
+    y: f32,
+    normal: [f32; 2],
+}
+
+impl ProfilePoint {
+    fn new(radius: f32, y: f32, normal_radius: f32, normal_y: f32) -> Self {
+        let length = (normal_radius * normal_radius + normal_y * normal_y).sqrt();
+        Self {
+            // Rounding errors, e.g. in sin(PI), mustn't move a point off the axis
+            radius: if radius.abs() < 1e-6 { 0.0 } else { radius },
+            y,
+            normal: [normal_radius / length, normal_y / length],
+        }
+    }
+
+    fn distance(&self, other: &ProfilePoint) -> f32 {
+        ((self.radius - other.radius).powi(2) + (self.y - other.y).powi(2)).sqrt()
+    }
+}
+
+struct MeshBuilder {
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+}
+
+impl MeshBuilder {
+    fn new() -> Self {
+        Self {
+            vertices: Vec::new(),
+            indices: Vec::new(),
+        }
+    }
+
+    fn vertex(
+        &mut self,
+        position: Vector3<f32>,
+        normal: Vector3<f32>,
+        tex_coords: [f32; 2],
+    ) -> u32 {
+        self.vertices.push(Vertex {
+            position: position.into(),
+            normal: normal.into(),
+            ambient: [0.2, 0.2, 0.2],
+            diffuse: [1.0, 1.0, 1.0],
+            specular_exponent: 1000.0,
+            tex_coords,
+            tangent: [0.0, 0.0, 0.0, 0.0],
+        });
+        (self.vertices.len() - 1) as u32
+    }
+
+    /// Adds a triangle whose vertices are counter-clockwise seen from its front.
+    fn triangle(&mut self, a: u32, b: u32, c: u32) {
+        // Stored clockwise like `Mesh::cube`: the projection flips Y, which makes them
+        // counter-clockwise on screen, the front face of the pipelines
+        self.indices.extend_from_slice(&[a, c, b]);
+    }
+
+    /// Adds a quad whose vertices are counter-clockwise seen from its front.
+    fn quad(&mut self, a: u32, b: u32, c: u32, d: u32) {
+        self.triangle(a, b, c);
+        self.triangle(a, c, d);
+    }
+
+    /// Turns `strips` of a profile around the Y axis in `sectors` slices. The strips go
+    /// from top to bottom, each with its normals on its left in the (radius, y) plane;
+    /// consecutive points of a strip are joined, separate strips aren't, so that their
+    /// normals can differ at a crease. `u` goes around the axis and `v` along the
+    /// profile.
+    fn lathe(&mut self, strips: &[&[ProfilePoint]], sectors: u32) {
+        let sectors = sectors.max(3);
+        let total_length: f32 = strips
+            .iter()
+            .flat_map(|strip| strip.windows(2))
+            .map(|pair| pair[0].distance(&pair[1]))
+            .sum();
+
+        let mut length = 0.0;
+        for strip in strips {
+            let mut previous: Option<(u32, ProfilePoint)> = None;
+
+            for point in strip.iter() {
+                if let Some((_, previous_point)) = previous {
+                    length += previous_point.distance(point);
+                }
+
+                let v = length / total_length;
+                let first = self.vertices.len() as u32;
+                for i in 0..=sectors {
+                    let u = i as f32 / sectors as f32;
+                    // The seam has exactly the positions of the first column
+                    let angle = if i == sectors { 0.0 } else { 2.0 * PI * u };
+                    let (sin, cos) = angle.sin_cos();
+                    let position = Vector3::new(point.radius * cos, point.y, -point.radius * sin);
+                    let normal = Vector3::new(
+                        point.normal[0] * cos,
+                        point.normal[1],
+                        -point.normal[0] * sin,
+                    );
+                    self.vertex(position, normal, [u, v]);
+                }
+
+                if let Some((previous_first, previous_point)) = previous {
+                    for i in 0..sectors {
+                        let (a, b) = (previous_first + i, previous_first + i + 1);
+                        let (c, d) = (first + i + 1, first + i);
+                        // A ring on the axis only needs one triangle per slice
+                        if previous_point.radius == 0.0 {
+                            self.triangle(a, d, c);
+                        } else if point.radius == 0.0 {
+                            self.triangle(a, d, b);
+                        } else {
+                            self.quad(a, d, c, b);
+                        }
+                    }
+                }
+
+                previous = Some((first, *point));
+            }
+        }
+    }
+
+    fn build(mut self) -> Mesh {
+        compute_tangents(&mut self.vertices, &self.indices);
+        Mesh::from_buffers(self.vertices, self.indices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn primitives() -> Vec<(&'static str, Mesh)> {
+        vec![
+            ("cube", cube(1.0)),
+            ("cuboid", cuboid(1.0, 2.0, 3.0)),
+            ("plane", plane(2.0, 1.0)),
+            ("grid", grid(2.0, 1.0, 4, 3)),
+            ("uv_sphere", uv_sphere(1.0, 16, 8)),
+            ("icosphere", icosphere(1.0, 2)),
+            ("cylinder", cylinder(0.5, 2.0, 12)),
+            ("cone", cone(0.5, 2.0, 12)),
+            ("torus", torus(1.0, 0.25, 16, 8)),
+            ("capsule", capsule(0.5, 1.0, 12, 4)),
+            ("arrow", arrow(1.0, 0.05, 8)),
+        ]
+    }
+
+    fn triangles(mesh: &Mesh) -> impl Iterator<Item = [&Vertex; 3]> {
+        mesh.indices.chunks(3).map(move |triangle| {
+            [
+                &mesh.vertices[triangle[0] as usize],
+                &mesh.vertices[triangle[1] as usize],
+                &mesh.vertices[triangle[2] as usize],
+            ]
+        })
+    }
+
+    #[test]
+    fn indices_are_in_range() {
+        for (name, mesh) in primitives() {
+            assert!(!mesh.indices.is_empty(), "{} has no triangles", name);
+            assert_eq!(mesh.indices.len() % 3, 0, "{} has a partial triangle", name);
+            for &index in &mesh.indices {
+                assert!(
+                    (index as usize) < mesh.vertices.len(),
+                    "{} has an index out of range: {}",
+                    name,
+                    index
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn normals_are_unit_vectors() {
+        for (name, mesh) in primitives() {
+            for vertex in &mesh.vertices {
+                let length = Vector3::from(vertex.normal).magnitude();
+                assert!(
+                    (length - 1.0).abs() < 1e-4,
+                    "{} has a normal of length {}",
+                    name,
+                    length
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn triangles_face_their_normals() {
+        for (name, mesh) in primitives() {
+            for corners in triangles(&mesh) {
+                let [a, b, c] = [
+                    Vector3::from(corners[0].position),
+                    Vector3::from(corners[1].position),
+                    Vector3::from(corners[2].position),
+                ];
+                // Stored clockwise, see `MeshBuilder::triangle`
+                let face_normal = (c - a).cross(b - a);
+                if face_normal.magnitude() < 1e-6 {
+                    continue;
+                }
+
+                let normal: Vector3<f32> = corners.iter().map(|v| Vector3::from(v.normal)).sum();
+                assert!(
+                    face_normal.dot(normal) > 0.0,
+                    "{} has a triangle facing away from its normals at {:?}",
+                    name,
+                    a
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn tex_coords_are_within_the_texture() {
+        for (name, mesh) in primitives() {
+            for vertex in &mesh.vertices {
+                let [u, v] = vertex.tex_coords;
+                // The seam copies of `icosphere` go past 1
+                let max_u = if name == "icosphere" { 1.5 } else { 1.0 };
+                assert!(
+                    (0.0..=max_u).contains(&u) && (0.0..=1.0).contains(&v),
+                    "{} has texture coordinates out of range: {:?}",
+                    name,
+                    vertex.tex_coords
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn icosphere_duplicates_the_seam() {
+        let mesh = icosphere(1.0, 2);
+
+        // Every copy past the seam has an original at the same position, one turn back
+        let copies: Vec<&Vertex> = mesh
+            .vertices
+            .iter()
+            .filter(|v| v.tex_coords[0] > 1.0)
+            .collect();
+        assert!(!copies.is_empty());
+        for copy in copies {
+            assert!(
+                mesh.vertices
+                    .iter()
+                    .any(|original| original.position == copy.position
+                        && (original.tex_coords[0] - (copy.tex_coords[0] - 1.0)).abs() < 1e-6),
+                "The seam copy at {:?} has no original",
+                copy.position
+            );
+        }
+
+        // So that no triangle wraps around the whole texture
+        for corners in triangles(&mesh) {
+            let us: Vec<f32> = corners.iter().map(|v| v.tex_coords[0]).collect();
+            let max = us.iter().cloned().fold(f32::MIN, f32::max);
+            let min = us.iter().cloned().fold(f32::MAX, f32::min);
+            assert!(
+                max - min < 0.5,
+                "A triangle spans u from {} to {}",
+                min,
+                max
+            );
+        }
+    }
+}
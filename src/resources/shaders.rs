@@ -14,6 +14,16 @@ pub mod basic {
     }
 }
 
+/// Draws many instances of a mesh in a single call, with `basic::fragment`.
+pub mod instanced {
+    pub mod vertex {
+        vulkano_shaders::shader! {
+            ty: "vertex",
+            path: "res/shaders/instanced.vs"
+        }
+    }
+}
+
 pub mod gizmo {
     pub mod vertex {
         vulkano_shaders::shader! {
@@ -0,0 +1,319 @@
+use std::time::{Duration, Instant};
+
+/// Measures the time between frames, as seen by the application: it can be paused,
+/// scaled, and clamped so that a long stall (a breakpoint, a window being dragged)
+/// doesn't turn into a huge step.
+pub struct Clock {
+    last_tick: Instant,
+    paused: bool,
+    time_scale: f32,
+    max_delta: Duration,
+    smoothing: f32,
+    delta: f32,
+    smoothed_delta: f32,
+    elapsed: f64,
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        Self {
+            last_tick: Instant::now(),
+            paused: false,
+            time_scale: 1.0,
+            max_delta: Duration::from_millis(250),
+            smoothing: 0.0,
+            delta: 0.0,
+            smoothed_delta: 0.0,
+            elapsed: 0.0,
+        }
+    }
+
+    /// Longer frames are counted as `max_delta`. Defaults to 250 ms.
+    pub fn with_max_delta(mut self, max_delta: Duration) -> Self {
+        self.max_delta = max_delta;
+        self
+    }
+
+    /// The weight of the previous frames in `smoothed_delta`, in `[0, 1)`. 0, the
+    /// default, disables smoothing.
+    pub fn with_smoothing(mut self, smoothing: f32) -> Self {
+        self.smoothing = smoothing.clamp(0.0, 0.99);
+        self
+    }
+
+    pub fn with_time_scale(mut self, time_scale: f32) -> Self {
+        self.time_scale = time_scale;
+        self
+    }
+
+    /// Measures the time since the previous tick and advances the clock by it.
+    /// Returns the new `delta`.
+    pub fn tick(&mut self) -> f32 {
+        let now = Instant::now();
+        let real_delta = now - self.last_tick;
+        self.last_tick = now;
+        self.advance(real_delta)
+    }
+
+    /// Advances the clock by `real_delta` instead of the measured time, e.g. to render
+    /// at a fixed frame rate. Returns the new `delta`.
+    pub fn advance(&mut self, real_delta: Duration) -> f32 {
+        self.delta = if self.paused {
+            0.0
+        } else {
+            real_delta.min(self.max_delta).as_secs_f32() * self.time_scale
+        };
+
+        self.smoothed_delta += (self.delta - self.smoothed_delta) * (1.0 - self.smoothing);
+        self.elapsed += self.delta as f64;
+        self.delta
+    }
+
+    /// The scaled time of the last tick in seconds, 0 while paused.
+    pub fn delta(&self) -> f32 {
+        self.delta
+    }
+
+    /// `delta` averaged over the previous frames, see `with_smoothing`.
+    pub fn smoothed_delta(&self) -> f32 {
+        self.smoothed_delta
+    }
+
+    /// The scaled time elapsed while not paused, in seconds.
+    pub fn elapsed(&self) -> f64 {
+        self.elapsed
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn time_scale(&self) -> f32 {
+        self.time_scale
+    }
+
+    /// Makes the time pass `time_scale` times faster, e.g. 0.5 for slow motion.
+    pub fn set_time_scale(&mut self, time_scale: f32) {
+        self.time_scale = time_scale;
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Turns variable frame times into a whole number of updates of a fixed duration,
+/// so that a simulation gives the same results whatever the frame rate.
+///
+/// The time left over after the last update is kept for the next frame; `alpha`
+/// tells how far the rendered frame is between the last two updates.
+pub struct FixedTimestep {
+    step: f32,
+    accumulator: f64,
+    max_steps: u32,
+}
+
+impl FixedTimestep {
+    /// Runs `rate` updates per second.
+    pub fn new(rate: f32) -> Self {
+        Self {
+            step: 1.0 / rate,
+            accumulator: 0.0,
+            max_steps: 8,
+        }
+    }
+
+    /// The most updates run for a single frame, the rest of the time being dropped.
+    /// Keeps a slow simulation from falling further behind every frame. Defaults to 8.
+    pub fn with_max_steps(mut self, max_steps: u32) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    /// The duration of an update in seconds.
+    pub fn step(&self) -> f32 {
+        self.step
+    }
+
+    /// Changes the rate, keeping the accumulated time.
+    pub fn set_rate(&mut self, rate: f32) {
+        self.step = 1.0 / rate;
+    }
+
+    /// Accumulates `delta` seconds and returns how many updates to run.
+    pub fn advance(&mut self, delta: f32) -> u32 {
+        let step = self.step as f64;
+        self.accumulator += delta as f64;
+
+        // Tolerates the rounding errors of deltas that are multiples of the step, such
+        // as 1 / 30 s with 60 updates per second
+        let steps = (self.accumulator / step + 1e-6).floor();
+        if steps > self.max_steps as f64 {
+            self.accumulator %= step;
+            return self.max_steps;
+        }
+
+        self.accumulator = (self.accumulator - steps * step).max(0.0);
+        steps as u32
+    }
+
+    /// In `[0, 1)`, the fraction of an update elapsed since the last one, to
+    /// interpolate between the previous and current states of the simulation.
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.step as f64) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-5,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn clock_advances_by_the_real_delta() {
+        let mut clock = Clock::new();
+
+        assert_close(clock.advance(ms(100)), 0.1);
+        assert_close(clock.advance(ms(50)), 0.05);
+        assert_close(clock.delta(), 0.05);
+        assert_close(clock.elapsed() as f32, 0.15);
+    }
+
+    #[test]
+    fn paused_clock_stands_still() {
+        let mut clock = Clock::new();
+        clock.advance(ms(100));
+
+        clock.pause();
+        assert!(clock.is_paused());
+        assert_eq!(clock.advance(ms(100)), 0.0);
+        assert_close(clock.elapsed() as f32, 0.1);
+
+        clock.resume();
+        assert_close(clock.advance(ms(100)), 0.1);
+        assert_close(clock.elapsed() as f32, 0.2);
+    }
+
+    #[test]
+    fn time_scale_scales_the_delta() {
+        let mut clock = Clock::new().with_time_scale(0.5);
+        assert_close(clock.advance(ms(100)), 0.05);
+
+        clock.set_time_scale(2.0);
+        assert_close(clock.time_scale(), 2.0);
+        assert_close(clock.advance(ms(100)), 0.2);
+        assert_close(clock.elapsed() as f32, 0.25);
+    }
+
+    #[test]
+    fn long_frames_are_clamped_to_max_delta() {
+        assert_close(Clock::new().advance(ms(1000)), 0.25);
+
+        let mut clock = Clock::new().with_max_delta(ms(100));
+        assert_close(clock.advance(ms(1000)), 0.1);
+        assert_close(clock.advance(ms(50)), 0.05);
+
+        // The scale applies to the clamped delta
+        let mut clock = Clock::new().with_max_delta(ms(100)).with_time_scale(2.0);
+        assert_close(clock.advance(ms(1000)), 0.2);
+    }
+
+    #[test]
+    fn smoothing_averages_the_previous_deltas() {
+        let mut clock = Clock::new();
+        clock.advance(ms(100));
+        assert_close(clock.smoothed_delta(), 0.1);
+
+        let mut clock = Clock::new().with_smoothing(0.5);
+        clock.advance(ms(100));
+        assert_close(clock.smoothed_delta(), 0.05);
+        clock.advance(ms(100));
+        assert_close(clock.smoothed_delta(), 0.075);
+        clock.advance(ms(0));
+        assert_close(clock.smoothed_delta(), 0.0375);
+        assert_eq!(clock.delta(), 0.0);
+    }
+
+    #[test]
+    fn smoothing_is_clamped_below_1() {
+        let mut clock = Clock::new().with_smoothing(1.0);
+        clock.advance(ms(100));
+        assert_close(clock.smoothed_delta(), 0.001);
+    }
+
+    #[test]
+    fn timestep_runs_whole_steps() {
+        let mut timestep = FixedTimestep::new(10.0);
+        assert_close(timestep.step(), 0.1);
+
+        assert_eq!(timestep.advance(0.05), 0);
+        assert_eq!(timestep.advance(0.05), 1);
+        assert_eq!(timestep.advance(0.25), 2);
+    }
+
+    #[test]
+    fn timestep_tolerates_rounding_errors() {
+        let mut timestep = FixedTimestep::new(60.0);
+        for _ in 0..100 {
+            assert_eq!(timestep.advance(1.0 / 30.0), 2);
+        }
+    }
+
+    #[test]
+    fn alpha_is_the_fraction_of_the_next_step() {
+        let mut timestep = FixedTimestep::new(10.0);
+        assert_close(timestep.alpha(), 0.0);
+
+        timestep.advance(0.25);
+        assert_close(timestep.alpha(), 0.5);
+
+        timestep.advance(0.025);
+        assert_close(timestep.alpha(), 0.75);
+    }
+
+    #[test]
+    fn set_rate_keeps_the_accumulated_time() {
+        let mut timestep = FixedTimestep::new(10.0);
+        timestep.advance(0.05);
+
+        timestep.set_rate(15.0);
+        assert_close(timestep.alpha(), 0.75);
+        assert_eq!(timestep.advance(1.0 / 60.0), 1);
+    }
+
+    #[test]
+    fn steps_are_clamped_to_max_steps() {
+        let mut timestep = FixedTimestep::new(10.0).with_max_steps(3);
+
+        assert_eq!(timestep.advance(1.05), 3);
+        // The time of the dropped steps is lost, the fraction of a step is kept
+        assert_close(timestep.alpha(), 0.5);
+        assert_eq!(timestep.advance(0.05), 1);
+    }
+}
@@ -1,6 +1,7 @@
 use cgmath::prelude::*;
 use cgmath::{Matrix4, Quaternion, Rad, Rotation3, Vector3};
 
+#[derive(Clone)]
 pub struct Transform {
     pub position: Vector3<f32>,
     pub scale: Vector3<f32>,
@@ -32,6 +33,16 @@ impl Transform {
         self.rotation = rotation * self.rotation;
     }
 
+    /// The transform `alpha` of the way from `previous` to `self`, e.g. to render
+    /// between two fixed updates.
+    pub fn interpolate(&self, previous: &Transform, alpha: f32) -> Transform {
+        Transform {
+            position: previous.position.lerp(self.position, alpha),
+            scale: previous.scale.lerp(self.scale, alpha),
+            rotation: previous.rotation.slerp(self.rotation, alpha),
+        }
+    }
+
     pub fn model_matrix(&self) -> Matrix4<f32> {
         let rotation_matrix: Matrix4<f32> = self.rotation.into();
         let scale_matrix = Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z);
@@ -0,0 +1,206 @@
+use std::sync::Arc;
+
+use vulkano::buffer::{
+    BufferAccess, BufferUsage, CpuAccessibleBuffer, DeviceLocalBuffer, TypedBufferAccess,
+};
+use vulkano::command_buffer::submit::SubmitCommandBufferBuilder;
+use vulkano::command_buffer::sys::{
+    Flags, Kind, UnsafeCommandBufferBuilder, UnsafeCommandBufferBuilderPipelineBarrier,
+};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBuffer};
+use vulkano::device::{Device, Queue};
+use vulkano::sync::{AccessFlagBits, Fence, GpuFuture, PipelineStages};
+
+use super::{Renderer, Result};
+
+/// Uploads `data` to a new device-local buffer through a staging buffer copied on the
+/// transfer queue. The buffer is exclusive to the graphics queue family; when the
+/// transfer queue belongs to another family, the ownership of the buffer is
+/// transferred to the graphics queue before returning.
+pub fn upload_to_device_local<T, I>(
+    renderer: &Renderer,
+    usage: BufferUsage,
+    data: I,
+) -> Result<Arc<DeviceLocalBuffer<[T]>>>
+where
+    T: Send + Sync + 'static,
+    I: ExactSizeIterator<Item = T>,
+{
+    let mut batch = UploadBatch::new(renderer)?;
+    let buffer = batch.upload(usage, data)?;
+    batch.submit()?;
+    Ok(buffer)
+}
+
+/// Uploads many buffers like `upload_to_device_local`, with a single submission and
+/// a single wait for all of them.
+pub struct UploadBatch<'a> {
+    renderer: &'a Renderer,
+    // Only `None` while a copy is being recorded
+    command_buffer_builder: Option<AutoCommandBufferBuilder>,
+    buffers: Vec<Arc<dyn BufferAccess + Send + Sync>>,
+}
+
+impl<'a> UploadBatch<'a> {
+    pub fn new(renderer: &'a Renderer) -> Result<Self> {
+        let command_buffer_builder = AutoCommandBufferBuilder::primary_one_time_submit(
+            renderer.device.clone(),
+            renderer.transfer_queue.family(),
+        )?;
+
+        Ok(Self {
+            renderer,
+            command_buffer_builder: Some(command_buffer_builder),
+            buffers: Vec::new(),
+        })
+    }
+
+    /// Records the upload of `data` to a new device-local buffer, which can't be used
+    /// before `submit` returns.
+    pub fn upload<T, I>(
+        &mut self,
+        usage: BufferUsage,
+        data: I,
+    ) -> Result<Arc<DeviceLocalBuffer<[T]>>>
+    where
+        T: Send + Sync + 'static,
+        I: ExactSizeIterator<Item = T>,
+    {
+        let staging_buffer = CpuAccessibleBuffer::from_iter(
+            self.renderer.device.clone(),
+            BufferUsage::transfer_source(),
+            data,
+        )?;
+
+        let buffer = DeviceLocalBuffer::array(
+            self.renderer.device.clone(),
+            staging_buffer.len(),
+            BufferUsage {
+                transfer_destination: true,
+                ..usage
+            },
+            std::iter::once(self.renderer.graphics_queue.family()),
+        )?;
+
+        let command_buffer_builder = self.command_buffer_builder.take().unwrap();
+        self.command_buffer_builder =
+            Some(command_buffer_builder.copy_buffer(staging_buffer, buffer.clone())?);
+        self.buffers.push(buffer.clone());
+
+        Ok(buffer)
+    }
+
+    /// Executes the copies on the transfer queue and waits for them, then transfers
+    /// the ownership of the buffers to the graphics queue if needed.
+    pub fn submit(mut self) -> Result<()> {
+        if self.buffers.is_empty() {
+            return Ok(());
+        }
+
+        let renderer = self.renderer;
+        self.command_buffer_builder
+            .take()
+            .unwrap()
+            .build()?
+            .execute(renderer.transfer_queue.clone())?
+            .then_signal_fence_and_flush()?
+            .wait(None)?;
+
+        if renderer.has_dedicated_transfer_queue() {
+            transfer_ownership(renderer, &self.buffers)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Releases `buffers` from the transfer queue family and acquires them on the graphics
+/// queue family, after copies on the transfer queue have completed.
+///
+/// vulkano's `AutoCommandBufferBuilder` can't record queue family ownership
+/// transfers, so the barriers are recorded in raw command buffers.
+pub fn transfer_ownership(
+    renderer: &Renderer,
+    buffers: &[Arc<dyn BufferAccess + Send + Sync>],
+) -> Result<()> {
+    let families = (
+        renderer.transfer_queue.family().id(),
+        renderer.graphics_queue.family().id(),
+    );
+
+    let mut release = UnsafeCommandBufferBuilderPipelineBarrier::new();
+    let mut acquire = UnsafeCommandBufferBuilderPipelineBarrier::new();
+
+    for buffer in buffers {
+        unsafe {
+            release.add_buffer_memory_barrier(
+                &**buffer,
+                PipelineStages {
+                    transfer: true,
+                    ..PipelineStages::none()
+                },
+                AccessFlagBits {
+                    transfer_write: true,
+                    ..AccessFlagBits::none()
+                },
+                PipelineStages {
+                    bottom_of_pipe: true,
+                    ..PipelineStages::none()
+                },
+                AccessFlagBits::none(),
+                false,
+                Some(families),
+                0,
+                buffer.size(),
+            );
+            acquire.add_buffer_memory_barrier(
+                &**buffer,
+                PipelineStages {
+                    top_of_pipe: true,
+                    ..PipelineStages::none()
+                },
+                AccessFlagBits::none(),
+                PipelineStages {
+                    all_commands: true,
+                    ..PipelineStages::none()
+                },
+                AccessFlagBits {
+                    memory_read: true,
+                    ..AccessFlagBits::none()
+                },
+                false,
+                Some(families),
+                0,
+                buffer.size(),
+            );
+        }
+    }
+
+    // The buffers outlive both submissions, which are waited on before returning
+    unsafe {
+        submit_barrier(&renderer.device, &renderer.transfer_queue, &release)?;
+        submit_barrier(&renderer.device, &renderer.graphics_queue, &acquire)
+    }
+}
+
+/// Submits a command buffer containing only `barrier` and waits for its completion.
+unsafe fn submit_barrier(
+    device: &Arc<Device>,
+    queue: &Arc<Queue>,
+    barrier: &UnsafeCommandBufferBuilderPipelineBarrier,
+) -> Result<()> {
+    let pool = Device::standard_command_pool(device, queue.family());
+    let mut builder =
+        UnsafeCommandBufferBuilder::new(&pool, Kind::primary(), Flags::OneTimeSubmit)?;
+    builder.pipeline_barrier(barrier);
+    let command_buffer = builder.build()?;
+
+    let fence = Fence::alloc(device.clone())?;
+
+    let mut submit = SubmitCommandBufferBuilder::new();
+    submit.add_command_buffer(&command_buffer);
+    submit.set_fence_signal(&fence);
+    submit.submit(queue)?;
+
+    Ok(fence.wait(None)?)
+}
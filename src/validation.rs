@@ -0,0 +1,89 @@
+use std::sync::{Arc, Mutex};
+
+use log::{error, info, trace, warn};
+use vulkano::instance::debug::{DebugCallback, Message, MessageSeverity, MessageType};
+use vulkano::instance::{layers_list, Instance};
+
+pub const VALIDATION_LAYER: &str = "VK_LAYER_KHRONOS_validation";
+
+/// Whether the Khronos validation layer is installed.
+pub fn validation_layer_available() -> bool {
+    layers_list()
+        .map(|mut layers| layers.any(|layer| layer.name() == VALIDATION_LAYER))
+        .unwrap_or(false)
+}
+
+/// Keeps the debug messenger alive and remembers the errors it reported.
+pub struct Validation {
+    _callback: DebugCallback,
+    errors: Arc<Mutex<Vec<String>>>,
+    panic_on_error: bool,
+}
+
+impl Validation {
+    /// Routes the validation messages of `instance` to `log`, at the level matching
+    /// their severity.
+    pub fn new(instance: &Arc<Instance>, panic_on_error: bool) -> Option<Self> {
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let callback_errors = errors.clone();
+
+        let severity = MessageSeverity {
+            error: true,
+            warning: true,
+            information: true,
+            verbose: true,
+        };
+
+        let callback = DebugCallback::new(instance, severity, MessageType::all(), move |msg| {
+            log_message(msg);
+            if msg.severity.error {
+                callback_errors
+                    .lock()
+                    .unwrap()
+                    .push(format!("[{}] {}", msg.layer_prefix, msg.description));
+            }
+        })
+        .ok()?;
+
+        Some(Self {
+            _callback: callback,
+            errors,
+            panic_on_error,
+        })
+    }
+
+    /// Returns and forgets the errors reported since the last call.
+    pub fn take_errors(&self) -> Vec<String> {
+        std::mem::take(&mut *self.errors.lock().unwrap())
+    }
+
+    /// Forgets the errors reported since the last call, and panics if there were some
+    /// and `panic_on_error` is set. The debug callback itself can't panic, vulkano
+    /// catches the unwinding.
+    pub fn check(&self) {
+        let errors = self.take_errors();
+        if self.panic_on_error && !errors.is_empty() {
+            panic!("Validation errors:\n{}", errors.join("\n"));
+        }
+    }
+}
+
+fn log_message(msg: &Message) {
+    let ty = if msg.ty.validation {
+        "validation"
+    } else if msg.ty.performance {
+        "performance"
+    } else {
+        "general"
+    };
+
+    if msg.severity.error {
+        error!("[{}] {}: {}", msg.layer_prefix, ty, msg.description);
+    } else if msg.severity.warning {
+        warn!("[{}] {}: {}", msg.layer_prefix, ty, msg.description);
+    } else if msg.severity.information {
+        info!("[{}] {}: {}", msg.layer_prefix, ty, msg.description);
+    } else {
+        trace!("[{}] {}: {}", msg.layer_prefix, ty, msg.description);
+    }
+}
@@ -1,4 +1,8 @@
+use cgmath::prelude::*;
+use cgmath::Matrix4;
+
 use super::color::Color;
+use super::transform::Transform;
 
 #[derive(Default, Copy, Clone)]
 pub struct Vertex {
@@ -7,6 +11,10 @@ pub struct Vertex {
     pub ambient: [f32; 3],
     pub diffuse: [f32; 3],
     pub specular_exponent: f32,
+    pub tex_coords: [f32; 2],
+    /// The direction of increasing `u`, and in `w` the handedness of the bitangent, see
+    /// `mesh::ops::compute_tangents`.
+    pub tangent: [f32; 4],
 }
 
 impl Vertex {
@@ -17,6 +25,8 @@ impl Vertex {
             ambient: [0.0, 0.0, 0.0],
             diffuse: [1.0, 1.0, 1.0],
             specular_exponent: 1.0,
+            tex_coords: [0.0, 0.0],
+            tangent: [0.0, 0.0, 0.0, 0.0],
         }
     }
 
@@ -27,6 +37,8 @@ impl Vertex {
             ambient: [0.0, 0.0, 0.0],
             diffuse: [r, g, b],
             specular_exponent: 1.0,
+            tex_coords: [0.0, 0.0],
+            tangent: [0.0, 0.0, 0.0, 0.0],
         }
     }
 }
@@ -37,6 +49,7 @@ pub struct VertexBuilder {
     pub ambient: Option<[f32; 3]>,
     pub diffuse: Option<[f32; 3]>,
     pub specular_exponent: Option<f32>,
+    pub tex_coords: Option<[f32; 2]>,
 }
 
 impl VertexBuilder {
@@ -47,6 +60,7 @@ impl VertexBuilder {
             ambient: None,
             diffuse: None,
             specular_exponent: None,
+            tex_coords: None,
         }
     }
 
@@ -75,6 +89,11 @@ impl VertexBuilder {
         self
     }
 
+    pub fn with_tex_coords(mut self, u: f32, v: f32) -> Self {
+        self.tex_coords = Some([u, v]);
+        self
+    }
+
     pub fn build(self) -> Result<Vertex, VertexBuildError> {
         if !self.is_valid() {
             Err(VertexBuildError)
@@ -85,6 +104,8 @@ impl VertexBuilder {
                 ambient: self.ambient.unwrap_or([0.0, 0.0, 0.0]),
                 diffuse: self.diffuse.unwrap(),
                 specular_exponent: self.specular_exponent.unwrap_or(1.0),
+                tex_coords: self.tex_coords.unwrap_or([0.0, 0.0]),
+                tangent: [0.0, 0.0, 0.0, 0.0],
             })
         }
     }
@@ -103,5 +124,41 @@ vulkano::impl_vertex!(
     normal,
     ambient,
     diffuse,
-    specular_exponent
+    specular_exponent,
+    tex_coords,
+    tangent
 );
+
+/// The attributes of an instance drawn with `InstancedRenderable`, read once per
+/// instance by `shaders::instanced::vertex`.
+#[derive(Default, Copy, Clone)]
+pub struct InstanceData {
+    pub model_matrix: [[f32; 4]; 4],
+    pub normal_matrix: [[f32; 4]; 4],
+    /// Multiplies the ambient and diffuse colors of the vertices, white by default.
+    pub color: [f32; 4],
+}
+
+impl InstanceData {
+    pub fn new(transform: &Transform) -> Self {
+        let model_matrix = transform.model_matrix();
+        // A transform scaled to zero has no normals to speak of
+        let normal_matrix = model_matrix
+            .invert()
+            .unwrap_or_else(Matrix4::identity)
+            .transpose();
+
+        Self {
+            model_matrix: model_matrix.into(),
+            normal_matrix: normal_matrix.into(),
+            color: [1.0, 1.0, 1.0, 1.0],
+        }
+    }
+
+    pub fn with_color(mut self, color: Color<f32>) -> Self {
+        self.color = [color.r, color.g, color.b, 1.0];
+        self
+    }
+}
+
+vulkano::impl_vertex!(InstanceData, model_matrix, normal_matrix, color);
@@ -0,0 +1,452 @@
+//! Renders reference scenes offscreen and compares them with the golden images in
+//! `tests/golden`, channel by channel with a small tolerance to absorb driver
+//! differences. On failure the rendered image and a diff are written to
+//! `target/golden-diffs`.
+//!
+//! The golden images are rendered by lavapipe, Mesa's software rasterizer, from Mesa
+//! 22.3 (`mesa-vulkan-drivers` on Debian 12), so that they don't depend on the GPU.
+//! The tests are skipped when no lavapipe device is available. Run them with
+//! `BLESS_GOLDENS=1` to write the golden images after an intended change; a missing
+//! golden image is a failure otherwise.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use cgmath::Vector3;
+use image::{Rgba, RgbaImage};
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer, CpuBufferPool};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBuffer};
+use vulkano::descriptor::descriptor_set::{FixedSizeDescriptorSetsPool, PersistentDescriptorSet};
+use vulkano::format::Format;
+use vulkano::instance::{Instance, InstanceExtensions, PhysicalDevice};
+use vulkano::sync::GpuFuture;
+
+use renderer::camera::CameraCenter;
+use renderer::color::Color;
+use renderer::format::select_depth_format;
+use renderer::offscreen::OffscreenTarget;
+use renderer::resources::loader::obj::ObjLoader;
+use renderer::resources::model::{Gizmo, InstancedRenderable, Renderable};
+use renderer::resources::pipelines::{
+    create_basic_pipeline, create_gizmo_pipeline, create_instanced_pipeline, create_render_pass,
+};
+use renderer::resources::{shaders, Material, Mesh, MeshInstance};
+use renderer::{Renderer, RendererConfig};
+
+const WIDTH: u32 = 256;
+const HEIGHT: u32 = 256;
+const COLOR_FORMAT: Format = Format::R8G8B8A8Unorm;
+
+/// Largest difference allowed on each channel of a pixel.
+const TOLERANCE: u8 = 2;
+
+/// The device the golden images are rendered with, matched by name.
+const GOLDEN_DEVICE: &str = "llvmpipe";
+
+/// A headless renderer on `GOLDEN_DEVICE`, or `None` when the machine doesn't have it.
+fn headless_renderer() -> Option<Renderer> {
+    let instance = Instance::new(None, &InstanceExtensions::none(), None).ok()?;
+    PhysicalDevice::enumerate(&instance).find(|device| device.name().contains(GOLDEN_DEVICE))?;
+
+    let config = RendererConfig {
+        width: WIDTH as f32,
+        height: HEIGHT as f32,
+        clear_color: [0.1, 0.1, 0.1],
+        validation: true,
+        panic_on_validation_error: true,
+        device: Some(String::from(GOLDEN_DEVICE)),
+        ..RendererConfig::default()
+    };
+
+    let mut renderer = Renderer::create_headless(config).unwrap();
+    renderer.state.set_dimensions(WIDTH as f32, HEIGHT as f32);
+    Some(renderer)
+}
+
+struct Scene<'a> {
+    models: &'a [MeshInstance],
+    /// Draws all the models with a single instanced call. They must share the mesh of
+    /// the first one.
+    instanced: bool,
+    gizmo: Option<Gizmo>,
+    camera: CameraCenter,
+}
+
+fn render(renderer: &Renderer, scene: Scene) -> RgbaImage {
+    let depth_format = select_depth_format(&renderer.device, renderer.config.depth_format)
+        .expect("No supported depth format");
+    let target = OffscreenTarget::new(
+        renderer.device.clone(),
+        [WIDTH, HEIGHT],
+        COLOR_FORMAT,
+        depth_format,
+        1,
+    )
+    .unwrap();
+
+    let render_pass =
+        create_render_pass(renderer.device.clone(), COLOR_FORMAT, depth_format, 1).unwrap();
+
+    let basic_vs = shaders::basic::vertex::Shader::load(renderer.device.clone()).unwrap();
+    let basic_fs = shaders::basic::fragment::Shader::load(renderer.device.clone()).unwrap();
+    let pipeline = create_basic_pipeline(
+        renderer.device.clone(),
+        &basic_vs,
+        &basic_fs,
+        render_pass.clone(),
+    )
+    .unwrap();
+
+    let gizmo_vs = shaders::gizmo::vertex::Shader::load(renderer.device.clone()).unwrap();
+    let gizmo_fs = shaders::gizmo::fragment::Shader::load(renderer.device.clone()).unwrap();
+    let gizmo_pipeline = create_gizmo_pipeline(
+        renderer.device.clone(),
+        &gizmo_vs,
+        &gizmo_fs,
+        render_pass.clone(),
+        1.0,
+    )
+    .unwrap();
+
+    let dynamic_state = renderer.state.dynamic_state();
+    let camera = &scene.camera;
+    let light_position = Vector3::new(0.0, 10.0, 0.0);
+
+    let mut command_buffer_builder = AutoCommandBufferBuilder::primary_one_time_submit(
+        renderer.device.clone(),
+        renderer.graphics_queue.family(),
+    )
+    .unwrap()
+    .begin_render_pass(
+        target.framebuffer(render_pass.clone()).unwrap(),
+        false,
+        vec![renderer.config.clear_color.into(), 1f32.into()],
+    )
+    .unwrap();
+
+    if let Some(gizmo) = scene.gizmo {
+        let vertex_buffer = CpuAccessibleBuffer::from_iter(
+            renderer.device.clone(),
+            BufferUsage::vertex_buffer(),
+            gizmo.vertices.into_iter(),
+        )
+        .unwrap();
+
+        let uniform_buffer = CpuAccessibleBuffer::from_data(
+            renderer.device.clone(),
+            BufferUsage::uniform_buffer(),
+            shaders::gizmo::vertex::ty::Data {
+                model: cgmath::Matrix4::from_scale(1.0).into(),
+                normal: cgmath::Matrix4::from_scale(1.0).into(),
+                view: camera.view_matrix().into(),
+                proj: renderer.state.projection.into(),
+                light_position: light_position.into(),
+                _dummy0: [0; 4],
+                view_position: camera.position().into(),
+            },
+        )
+        .unwrap();
+
+        let set = Arc::new(
+            PersistentDescriptorSet::start(gizmo_pipeline.clone(), 0)
+                .add_buffer(uniform_buffer)
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+
+        command_buffer_builder = command_buffer_builder
+            .draw(
+                gizmo_pipeline,
+                &dynamic_state,
+                vec![vertex_buffer],
+                set,
+                (),
+            )
+            .unwrap();
+    }
+
+    let world_data_buffer = CpuAccessibleBuffer::from_data(
+        renderer.device.clone(),
+        BufferUsage::uniform_buffer(),
+        shaders::basic::vertex::ty::WorldData {
+            view_matrix: camera.view_matrix().into(),
+            projection_matrix: renderer.state.projection.into(),
+            light_position: light_position.into(),
+            _dummy0: [0; 4],
+            view_position: camera.position().into(),
+        },
+    )
+    .unwrap();
+
+    let world_set = Arc::new(
+        PersistentDescriptorSet::start(pipeline.clone(), 0)
+            .add_buffer(world_data_buffer)
+            .unwrap()
+            .build()
+            .unwrap(),
+    );
+
+    let model_data_buffer = CpuBufferPool::uniform_buffer(renderer.device.clone());
+    let mut model_pool = FixedSizeDescriptorSetsPool::new(pipeline.clone(), 1);
+
+    if scene.instanced {
+        let instanced_vs =
+            shaders::instanced::vertex::Shader::load(renderer.device.clone()).unwrap();
+        let instanced_pipeline = create_instanced_pipeline(
+            renderer.device.clone(),
+            &instanced_vs,
+            &basic_fs,
+            render_pass.clone(),
+        )
+        .unwrap();
+
+        let instance_buffer = CpuAccessibleBuffer::from_iter(
+            renderer.device.clone(),
+            BufferUsage::vertex_buffer(),
+            scene.models.iter().map(MeshInstance::instance_data),
+        )
+        .unwrap();
+
+        command_buffer_builder = scene.models[0]
+            .mesh
+            .render_instanced(
+                command_buffer_builder,
+                instanced_pipeline,
+                &dynamic_state,
+                instance_buffer,
+                world_set.clone(),
+            )
+            .unwrap();
+    } else {
+        for model in scene.models {
+            command_buffer_builder = model
+                .render(
+                    command_buffer_builder,
+                    pipeline.clone(),
+                    &dynamic_state,
+                    world_set.clone(),
+                    &model_data_buffer,
+                    &mut model_pool,
+                )
+                .unwrap();
+        }
+    }
+
+    let command_buffer_builder = command_buffer_builder.end_render_pass().unwrap();
+    target
+        .copy_to_readback(command_buffer_builder)
+        .unwrap()
+        .build()
+        .unwrap()
+        .execute(renderer.graphics_queue.clone())
+        .unwrap()
+        .then_signal_fence_and_flush()
+        .unwrap()
+        .wait(None)
+        .unwrap();
+
+    renderer.check_validation();
+    target.read().unwrap()
+}
+
+/// Compares `actual` with the golden image `name`, or overwrites the golden image with
+/// it when `BLESS_GOLDENS` is set.
+fn check_golden(name: &str, actual: &RgbaImage) {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let golden_path = root.join("tests").join("golden").join(format!("{}.png", name));
+
+    if std::env::var_os("BLESS_GOLDENS").is_some() {
+        actual.save(&golden_path).unwrap();
+        eprintln!("Wrote golden image {}", golden_path.display());
+        return;
+    }
+
+    if !golden_path.exists() {
+        panic!(
+            "Golden image {} is missing, render it with BLESS_GOLDENS=1",
+            golden_path.display()
+        );
+    }
+
+    let expected = image::open(&golden_path).unwrap().to_rgba();
+    check_same(name, &expected, actual);
+}
+
+/// Compares two renderings of the same scene, writing `actual` and the diff on failure.
+fn check_same(name: &str, expected: &RgbaImage, actual: &RgbaImage) {
+    let (diff, mismatches) = diff_images(expected, actual);
+    if mismatches == 0 {
+        return;
+    }
+
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let diff_dir: PathBuf = root.join("target").join("golden-diffs");
+    std::fs::create_dir_all(&diff_dir).unwrap();
+    let actual_path = diff_dir.join(format!("{}.actual.png", name));
+    let diff_path = diff_dir.join(format!("{}.diff.png", name));
+    actual.save(&actual_path).unwrap();
+    diff.save(&diff_path).unwrap();
+
+    panic!(
+        "{} pixels of `{}` differ from the expected image by more than {}, see {} and {}",
+        mismatches,
+        name,
+        TOLERANCE,
+        actual_path.display(),
+        diff_path.display()
+    );
+}
+
+/// Counts the pixels differing by more than `TOLERANCE` on any channel. The diff image
+/// shows them in red over a dimmed copy of the expected image.
+fn diff_images(expected: &RgbaImage, actual: &RgbaImage) -> (RgbaImage, usize) {
+    if expected.dimensions() != actual.dimensions() {
+        let (width, height) = actual.dimensions();
+        let all_red = RgbaImage::from_pixel(width, height, Rgba([255, 0, 0, 255]));
+        return (all_red, (width * height) as usize);
+    }
+
+    let mut mismatches = 0;
+    let diff = RgbaImage::from_fn(actual.width(), actual.height(), |x, y| {
+        let e = expected.get_pixel(x, y);
+        let a = actual.get_pixel(x, y);
+
+        let differs = e
+            .data
+            .iter()
+            .zip(a.data.iter())
+            .any(|(&e, &a)| (e as i16 - a as i16).abs() > TOLERANCE as i16);
+
+        if differs {
+            mismatches += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            let luma = (e[0] as u32 + e[1] as u32 + e[2] as u32) / 12;
+            Rgba([luma as u8, luma as u8, luma as u8, 255])
+        }
+    });
+
+    (diff, mismatches)
+}
+
+/// Nine cubes of different colors sharing a single mesh, in a 3x3 grid.
+fn cube_grid_models(renderer: &Renderer) -> Vec<MeshInstance> {
+    let mut cube = Mesh::cube(0.4, Color::<f32>::WHITE);
+    cube.bake(renderer).unwrap();
+    let cube = Arc::new(cube);
+
+    (0..9)
+        .map(|i| {
+            let color = Color::<f32>::new(0.3 + 0.07 * i as f32, 0.5, 1.0 - 0.07 * i as f32);
+            let mut model = MeshInstance::new(cube.clone()).with_material(Material::new(color));
+            let (x, z) = ((i % 3) as f32 - 1.0, (i / 3) as f32 - 1.0);
+            model.transform.translate(Vector3::new(x * 1.5, 0.0, z * 1.5));
+            model
+        })
+        .collect()
+}
+
+#[test]
+fn cube_grid() {
+    let renderer = match headless_renderer() {
+        Some(renderer) => renderer,
+        None => return eprintln!("No {} device, skipping", GOLDEN_DEVICE),
+    };
+
+    let models = cube_grid_models(&renderer);
+
+    let mut camera = CameraCenter::new();
+    camera.radius = 6.0;
+
+    let image = render(
+        &renderer,
+        Scene {
+            models: &models,
+            instanced: false,
+            gizmo: None,
+            camera,
+        },
+    );
+    check_golden("cube_grid", &image);
+}
+
+/// The instanced path must draw exactly what one draw call per model does.
+#[test]
+fn instanced_cube_grid() {
+    let renderer = match headless_renderer() {
+        Some(renderer) => renderer,
+        None => return eprintln!("No {} device, skipping", GOLDEN_DEVICE),
+    };
+
+    let models = cube_grid_models(&renderer);
+
+    let scene = |instanced| {
+        let mut camera = CameraCenter::new();
+        camera.radius = 6.0;
+        Scene {
+            models: &models,
+            instanced,
+            gizmo: None,
+            camera,
+        }
+    };
+    let image = render(&renderer, scene(true));
+    check_golden("instanced_cube_grid", &image);
+    check_same(
+        "instanced_cube_grid",
+        &render(&renderer, scene(false)),
+        &image,
+    );
+}
+
+#[test]
+fn gizmo() {
+    let renderer = match headless_renderer() {
+        Some(renderer) => renderer,
+        None => return eprintln!("No {} device, skipping", GOLDEN_DEVICE),
+    };
+
+    let mut camera = CameraCenter::new();
+    camera.update_yaw(std::f32::consts::FRAC_PI_4);
+
+    let image = render(
+        &renderer,
+        Scene {
+            models: &[],
+            instanced: false,
+            gizmo: Some(Gizmo::new(1.0)),
+            camera,
+        },
+    );
+    check_golden("gizmo", &image);
+}
+
+#[test]
+fn obj_model() {
+    let renderer = match headless_renderer() {
+        Some(renderer) => renderer,
+        None => return eprintln!("No {} device, skipping", GOLDEN_DEVICE),
+    };
+
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/pyramid.obj");
+    let mut mesh = ObjLoader::load(&path).expect("Failed to load the pyramid");
+    mesh.bake(&renderer).unwrap();
+    let mut model = MeshInstance::new(Arc::new(mesh));
+    model.transform.translate(Vector3::new(0.0, -0.5, 0.0));
+
+    let mut camera = CameraCenter::new();
+    camera.radius = 4.0;
+    camera.update_yaw(0.3);
+
+    let image = render(
+        &renderer,
+        Scene {
+            models: &[model],
+            instanced: false,
+            gizmo: None,
+            camera,
+        },
+    );
+    check_golden("obj_model", &image);
+}
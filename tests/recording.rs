@@ -0,0 +1,201 @@
+//! Renders offscreen while presented frames are still in flight, as `renderer::record`
+//! and the F12 screenshot fallback do with a window. The frames are submitted to an
+//! offscreen target instead of a swapchain, so no window is needed.
+//!
+//! The tests are skipped when no Vulkan device is available.
+
+use std::sync::Arc;
+
+use cgmath::Vector3;
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer};
+use vulkano::descriptor::descriptor_set::PersistentDescriptorSet;
+use vulkano::format::Format;
+use vulkano::framebuffer::RenderPassAbstract;
+use vulkano::instance::{Instance, InstanceExtensions, PhysicalDevice};
+use vulkano::pipeline::GraphicsPipelineAbstract;
+use vulkano::sync::GpuFuture;
+
+use renderer::app::{record_frame, render_offscreen};
+use renderer::camera::CameraCenter;
+use renderer::color::Color;
+use renderer::format::select_depth_format;
+use renderer::offscreen::OffscreenTarget;
+use renderer::presenter::{FramesInFlight, FRAMES_IN_FLIGHT};
+use renderer::resources::model::InstancedRenderable;
+use renderer::resources::pipelines::{create_instanced_pipeline, create_render_pass};
+use renderer::resources::{shaders, Mesh};
+use renderer::vertex::InstanceData;
+use renderer::{App, FrameContext, Renderer, RendererConfig};
+
+const WIDTH: u32 = 64;
+const HEIGHT: u32 = 64;
+const COLOR_FORMAT: Format = Format::R8G8B8A8Unorm;
+
+/// A headless renderer, or `None` when the machine has no Vulkan device.
+fn headless_renderer() -> Option<Renderer> {
+    let instance = Instance::new(None, &InstanceExtensions::none(), None).ok()?;
+    PhysicalDevice::enumerate(&instance).next()?;
+
+    let config = RendererConfig {
+        width: WIDTH as f32,
+        height: HEIGHT as f32,
+        ..RendererConfig::default()
+    };
+
+    let mut renderer = Renderer::create_headless(config).unwrap();
+    renderer.state.set_dimensions(WIDTH as f32, HEIGHT as f32);
+    Some(renderer)
+}
+
+/// Writes the instances of a cube to the buffer of the frame index every frame, like the
+/// demo does.
+#[derive(Default)]
+struct CubeApp {
+    pipeline: Option<Arc<dyn GraphicsPipelineAbstract + Send + Sync>>,
+    cube: Option<Arc<Mesh>>,
+    instance_buffers: Vec<Arc<CpuAccessibleBuffer<[InstanceData]>>>,
+    frames_rendered: u32,
+}
+
+impl App for CubeApp {
+    fn init(
+        &mut self,
+        renderer: &mut Renderer,
+        render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    ) -> renderer::Result<()> {
+        let vs = shaders::instanced::vertex::Shader::load(renderer.device.clone())?;
+        let fs = shaders::basic::fragment::Shader::load(renderer.device.clone())?;
+        self.pipeline = Some(create_instanced_pipeline(
+            renderer.device.clone(),
+            &vs,
+            &fs,
+            render_pass,
+        )?);
+
+        let mut cube = Mesh::cube(0.5, Color::<f32>::WHITE);
+        cube.bake(renderer)?;
+        self.cube = Some(Arc::new(cube));
+
+        for _ in 0..FRAMES_IN_FLIGHT {
+            self.instance_buffers.push(CpuAccessibleBuffer::from_iter(
+                renderer.device.clone(),
+                BufferUsage::vertex_buffer(),
+                std::iter::once(InstanceData::default()),
+            )?);
+        }
+
+        Ok(())
+    }
+
+    fn render(&mut self, context: &mut FrameContext) -> renderer::Result<()> {
+        let renderer = context.renderer;
+        let pipeline = self.pipeline.clone().unwrap();
+
+        // Fails with `GpuLocked` if a submitted frame still uses the buffer
+        let instance_buffer = self.instance_buffers[context.index].clone();
+        instance_buffer.write()?[0] = InstanceData::default().with_color(Color::<f32>::new(
+            1.0,
+            0.1 * self.frames_rendered as f32,
+            0.0,
+        ));
+        self.frames_rendered += 1;
+
+        let camera = CameraCenter::new();
+        let world_data_buffer = CpuAccessibleBuffer::from_data(
+            renderer.device.clone(),
+            BufferUsage::uniform_buffer(),
+            shaders::basic::vertex::ty::WorldData {
+                view_matrix: camera.view_matrix().into(),
+                projection_matrix: renderer.state.projection.into(),
+                light_position: Vector3::new(0.0, 10.0, 0.0).into(),
+                _dummy0: [0; 4],
+                view_position: camera.position().into(),
+            },
+        )?;
+        let world_set = Arc::new(
+            PersistentDescriptorSet::start(pipeline.clone(), 0)
+                .add_buffer(world_data_buffer)?
+                .build()?,
+        );
+
+        let cube = self.cube.as_ref().unwrap();
+        let dynamic_state = context.dynamic_state.clone();
+        context.record(|command_buffer_builder| {
+            cube.render_instanced(
+                command_buffer_builder,
+                pipeline,
+                &dynamic_state,
+                instance_buffer,
+                world_set,
+            )
+        })
+    }
+}
+
+/// Each iteration presents a frame without waiting for it, then records it offscreen,
+/// with the resources of index 0 that the frame in flight may still use.
+#[test]
+fn record_while_frames_in_flight() {
+    let mut renderer = match headless_renderer() {
+        Some(renderer) => renderer,
+        None => return eprintln!("No Vulkan device, skipping"),
+    };
+
+    let depth_format = select_depth_format(&renderer.device, renderer.config.depth_format)
+        .expect("No supported depth format");
+    let render_pass =
+        create_render_pass(renderer.device.clone(), COLOR_FORMAT, depth_format, 1).unwrap();
+
+    let mut app = CubeApp::default();
+    app.init(&mut renderer, render_pass.clone()).unwrap();
+
+    // Stand-ins for the swapchain image and for the recording target
+    let presented = OffscreenTarget::new(
+        renderer.device.clone(),
+        [WIDTH, HEIGHT],
+        COLOR_FORMAT,
+        depth_format,
+        1,
+    )
+    .unwrap();
+    let recorded = OffscreenTarget::new(
+        renderer.device.clone(),
+        [WIDTH, HEIGHT],
+        COLOR_FORMAT,
+        depth_format,
+        1,
+    )
+    .unwrap();
+
+    let mut frames = FramesInFlight::new();
+    for _ in 0..2 * FRAMES_IN_FLIGHT {
+        let index = frames.begin_frame().unwrap();
+        let framebuffer = presented.framebuffer(render_pass.clone()).unwrap();
+        let command_buffer = record_frame(&mut app, &renderer, framebuffer, index, 0.0)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let rendered_future: Box<dyn GpuFuture + Send + Sync> = Box::new(
+            frames
+                .previous_frame_end(&renderer.device)
+                .then_execute(renderer.graphics_queue.clone(), command_buffer)
+                .unwrap(),
+        );
+        let fence = rendered_future.then_signal_fence_and_flush().unwrap();
+        frames.end_frame(index, Some(Arc::new(fence)));
+
+        let image = render_offscreen(
+            &mut app,
+            &mut renderer,
+            &mut frames,
+            &recorded,
+            &render_pass,
+            0.0,
+        )
+        .unwrap();
+        assert_eq!(image.dimensions(), (WIDTH, HEIGHT));
+    }
+
+    assert_eq!(app.frames_rendered, 4 * FRAMES_IN_FLIGHT as u32);
+}